@@ -0,0 +1,68 @@
+//! Write files atomically, so that an interruption never leaves a truncated file.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Write to a temporary file with `write`, and then rename it to `path`.
+///
+/// The previous version of `path`, if any, is kept as `<path>.bak`.
+pub fn write_with(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    replace_with(path, true, write)
+}
+
+/// [`write_with`] without keeping the previous version.
+pub fn write_with_no_backup(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    replace_with(path, false, write)
+}
+
+fn replace_with(
+    path: &Path,
+    backup: bool,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let temp = sibling(path, ".", ".tmp");
+
+    let result = (|| {
+        let mut file = BufWriter::new(File::create(&temp)?);
+        write(&mut file)?;
+        file.into_inner()?.sync_all()
+    })();
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp);
+        return Err(err);
+    }
+
+    // Copy rather than rename, so that `path` always exists
+    if backup && path.try_exists()? {
+        fs::copy(path, backup_path(path))?;
+    }
+    fs::rename(&temp, path)
+}
+
+/// [`write_with`] for contents at hand.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    write_with(path, |file| file.write_all(contents.as_ref()))
+}
+
+/// Where the previous version of `path` is kept, e.g. `data/episodes.json.bak`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    sibling(path, "", ".bak")
+}
+
+/// A path in the same directory, with the file name decorated, e.g. `data/.episodes.json.tmp`.
+fn sibling(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(prefix);
+    name.push(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
+}
@@ -0,0 +1,258 @@
+//! Versioned envelopes of data caches, with migrations from older versions.
+//!
+//! A cache file looks like `{"version": 1, "data": …}`.
+//! Files without an envelope are version 0, e.g. those in old `data.7z` downloads.
+
+use std::io;
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value, json};
+
+use crate::data::{Link, LinkRole};
+
+/// Upgrade data from the previous version.
+type Migration = fn(Value) -> Result<Value, String>;
+
+#[derive(Serialize)]
+struct Envelope<'a, T> {
+    version: usize,
+    data: &'a T,
+}
+
+/// The schema of a cache file.
+#[derive(Debug)]
+pub struct Schema {
+    /// Name of the cache, for error messages
+    name: &'static str,
+    /// The `i`-th migration upgrades version `i` to `i + 1`
+    migrations: &'static [Migration],
+}
+
+/// `episodes.json`
+pub const EPISODES: Schema = Schema {
+    name: "episodes",
+    migrations: &[episodes_v0_to_v1],
+};
+
+/// `short_urls.json`
+pub const SHORT_URLS: Schema = Schema {
+    name: "short URLs",
+    migrations: &[short_urls_v0_to_v1],
+};
+
+impl Schema {
+    /// The current version.
+    pub fn version(&self) -> usize {
+        self.migrations.len()
+    }
+
+    /// Parse a cache file of any version, and migrate it to the current version.
+    pub fn load<T: DeserializeOwned>(&self, json: &str) -> io::Result<T> {
+        let (version, mut data) = match serde_json::from_str(json)? {
+            Value::Object(mut envelope) if envelope.contains_key("version") => {
+                let version = envelope
+                    .get("version")
+                    .and_then(Value::as_u64)
+                    .ok_or_else(|| self.invalid_data("the version is not a number".to_owned()))?;
+                let data = envelope.remove("data").unwrap_or_default();
+                (version as usize, data)
+            }
+            bare => (0, bare),
+        };
+
+        if version > self.version() {
+            return Err(self.invalid_data(format!(
+                "version {version} is newer than the supported version {}; please upgrade typechat-link",
+                self.version()
+            )));
+        }
+        for (from, migrate) in self.migrations.iter().enumerate().skip(version) {
+            data = migrate(data).map_err(|err| {
+                self.invalid_data(format!("failed to migrate from version {from}: {err}"))
+            })?;
+        }
+
+        Ok(serde_json::from_value(data)?)
+    }
+
+    /// Dump data of the current version to a cache file, optionally indented.
+    pub fn dump<T: Serialize>(&self, data: &T, pretty: bool) -> serde_json::Result<String> {
+        let envelope = Envelope {
+            version: self.version(),
+            data,
+        };
+        if pretty {
+            serde_json::to_string_pretty(&envelope).map(|json| json + "\n")
+        } else {
+            serde_json::to_string(&envelope)
+        }
+    }
+
+    fn invalid_data(&self, message: String) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} cache: {message}", self.name),
+        )
+    }
+}
+
+/// `[[episode, links], …]` → `[{…episode, "links": links}, …]`
+///
+/// Links stored as bare URLs become records, and missing fields are filled.
+fn episodes_v0_to_v1(data: Value) -> Result<Value, String> {
+    let Value::Array(entries) = data else {
+        return Err("expect a list of episodes".to_owned());
+    };
+    entries
+        .into_iter()
+        .map(|entry| {
+            let (mut episode, links) = serde_json::from_value::<(Map<_, _>, Vec<Value>)>(entry)
+                .map_err(|err| format!("expect a pair of an episode and its links: {err}"))?;
+            for field in ["categories", "tags"] {
+                episode.entry(field).or_insert_with(|| json!([]));
+            }
+            let links = links
+                .into_iter()
+                .enumerate()
+                .map(|(index, link)| link_v0_to_v1(index, link))
+                .collect::<Result<_, _>>()?;
+            episode.insert("links".to_owned(), Value::Array(links));
+            Ok(Value::Object(episode))
+        })
+        .collect::<Result<_, _>>()
+        .map(Value::Array)
+}
+
+/// A link, either the expanded URL or a record without newer fields.
+fn link_v0_to_v1(index: usize, link: Value) -> Result<Value, String> {
+    let (mut link, has_role) = match link {
+        Value::String(url) => (Link::new(url), false),
+        Value::Object(mut record) => {
+            let has_role = record.contains_key("role");
+            record.entry("text").or_insert_with(|| json!(""));
+            record.entry("index").or_insert_with(|| json!(index));
+            record.entry("context").or_insert(Value::Null);
+            record.entry("section").or_insert(Value::Null);
+            record
+                .entry("role")
+                .or_insert_with(|| json!(LinkRole::Reference));
+            let link = serde_json::from_value(Value::Object(record))
+                .map_err(|err| format!("invalid link #{index}: {err}"))?;
+            (link, has_role)
+        }
+        _ => return Err(format!("invalid link #{index}: expect a URL or a record")),
+    };
+    link.index = index;
+    if !has_role {
+        link.role = LinkRole::guess(&link);
+    }
+    serde_json::to_value(link).map_err(|err| err.to_string())
+}
+
+/// Expanded URLs stored as strings become redirect chains of a single hop.
+fn short_urls_v0_to_v1(data: Value) -> Result<Value, String> {
+    let Value::Object(mut short_urls) = data else {
+        return Err("expect a map of short URLs".to_owned());
+    };
+    for expansion in short_urls.values_mut() {
+        if let Value::String(url) = expansion {
+            *expansion = json!({ "hops": [{ "status": null, "url": url }], "status": null });
+        }
+    }
+    Ok(Value::Object(short_urls))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Driver;
+
+    /// `episodes.json` in old `data.7z` downloads
+    const EPISODES_V0: &str = r#"[
+        [
+            {"name": "字谈字畅 250：增补修订传心意", "url": "https://www.thetype.com/typechat/ep-250/"},
+            ["https://en.wikipedia.org/wiki/Emoji", "https://www.thetype.com/feed/typechat/"]
+        ],
+        [
+            {"name": "字谈字畅 1：开张", "url": "https://www.thetype.com/typechat/ep-001/"},
+            [{"href": "http://t.cn/zHVwH1H", "url": "https://en.wikipedia.org/wiki/Type", "canonical": "https://en.wikipedia.org/wiki/Type"}]
+        ]
+    ]"#;
+
+    /// `short_urls.json` in old `data.7z` downloads
+    const SHORT_URLS_V0: &str = r#"{"http://t.cn/zHVwH1H": "https://en.wikipedia.org/wiki/Type"}"#;
+
+    #[test]
+    fn episodes_v0() {
+        let episodes: Value = EPISODES.load(EPISODES_V0).unwrap();
+        let ep_250 = &episodes[0];
+        assert_eq!(ep_250["url"], "https://www.thetype.com/typechat/ep-250/");
+        assert_eq!(ep_250["tags"], json!([]));
+
+        let links = ep_250["links"].as_array().unwrap();
+        assert_eq!(links[0]["href"], "https://en.wikipedia.org/wiki/Emoji");
+        assert_eq!(links[0]["canonical"], "https://en.wikipedia.org/wiki/Emoji");
+        assert_eq!(
+            (&links[0]["index"], &links[1]["index"]),
+            (&json!(0), &json!(1))
+        );
+        // Roles are guessed by URLs
+        assert_eq!(links[0]["role"], "reference");
+        assert_eq!(links[1]["role"], "subscription");
+
+        // Records without newer fields are filled in
+        let link = &episodes[1]["links"][0];
+        assert_eq!(link["url"], "https://en.wikipedia.org/wiki/Type");
+        assert_eq!(
+            (&link["text"], &link["role"]),
+            (&json!(""), &json!("reference"))
+        );
+    }
+
+    #[test]
+    fn short_urls_v0() {
+        let short_urls: Value = SHORT_URLS.load(SHORT_URLS_V0).unwrap();
+        assert_eq!(
+            short_urls["http://t.cn/zHVwH1H"],
+            json!({
+                "hops": [{ "status": null, "url": "https://en.wikipedia.org/wiki/Type" }],
+                "status": null
+            })
+        );
+    }
+
+    #[test]
+    fn dump_v1() {
+        let driver =
+            Driver::from_cache(Some(EPISODES_V0.to_owned()), Some(SHORT_URLS_V0.to_owned()))
+                .unwrap();
+        let (episodes, short_urls) = driver.to_cache(false).unwrap();
+
+        let episodes: Value = serde_json::from_str(&episodes).unwrap();
+        assert_eq!(episodes["version"], 1);
+        // Sorted by episode IDs
+        let data = &episodes["data"];
+        assert_eq!(data[0]["url"], "https://www.thetype.com/typechat/ep-001/");
+        assert_eq!(data[1]["links"].as_array().unwrap().len(), 2);
+
+        let short_urls: Value = serde_json::from_str(&short_urls).unwrap();
+        assert_eq!(short_urls["version"], 1);
+        assert_eq!(
+            short_urls["data"]["http://t.cn/zHVwH1H"]["hops"][0]["url"],
+            "https://en.wikipedia.org/wiki/Type"
+        );
+
+        // A dumped cache loads as is
+        let reloaded: Value = EPISODES.load(&episodes.to_string()).unwrap();
+        assert_eq!(reloaded, *data);
+    }
+
+    #[test]
+    fn newer_version() {
+        let err = EPISODES
+            .load::<Value>(r#"{"version": 99, "data": []}"#)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("version 99 is newer"), "{err}");
+    }
+}
@@ -0,0 +1,175 @@
+//! Canonicalize URLs, so that different spellings of the same URL are counted once.
+//!
+//! The canonical form is an identity, not necessarily a working address.
+//! For example, `http://` is always replaced with `https://`.
+
+use reqwest::Url;
+
+/// Canonicalize an absolute HTTP(S) URL.
+///
+/// - Use `https://`.
+/// - Lowercase the host and remove the default port. (done by the parser)
+/// - Percent-encode non-ASCII characters, and uppercase the hex digits of valid escapes.
+/// - Remove tracking parameters like `utm_*`, and an empty query.
+/// - Remove the fragment, unless it is a route of a single-page app (`#/…` or `#!/…`).
+/// - Remove a single trailing slash, unless the path would become the root.
+///
+/// Return `None` for relative URLs, other schemes, and malformed URLs.
+pub fn canonicalize(url: &str) -> Option<String> {
+    let mut url = Url::parse(url).ok()?;
+    match url.scheme() {
+        "https" => {}
+        "http" => url.set_scheme("https").ok()?,
+        _ => return None,
+    }
+
+    // Query, filtered as raw text, so that the rest is never re-encoded
+    if let Some(query) = url.query() {
+        let pairs: Vec<_> = query
+            .split('&')
+            .filter(|pair| {
+                let key = pair.split_once('=').map_or(*pair, |(key, _)| key);
+                !pair.is_empty() && !is_tracking_param(key)
+            })
+            .collect();
+        if pairs.is_empty() {
+            url.set_query(None);
+        } else {
+            let query = pairs.join("&");
+            url.set_query(Some(&query));
+        }
+    }
+
+    // Fragment
+    if !url
+        .fragment()
+        .is_some_and(|f| f.starts_with('/') || f.starts_with("!/"))
+    {
+        url.set_fragment(None);
+    }
+
+    // Path, removing a single trailing slash, unless it would collapse to the root (e.g. `//`)
+    if let Some(path) = url.path().strip_suffix('/')
+        && !matches!(path, "" | "/")
+    {
+        let path = path.to_owned();
+        url.set_path(&path);
+    }
+
+    Some(uppercase_percent_encoding(url.as_str()))
+}
+
+/// Check if a query parameter is only for tracking.
+fn is_tracking_param(key: &str) -> bool {
+    key.starts_with("utm_") || matches!(key, "fbclid" | "gclid" | "spm")
+}
+
+/// Uppercase hex digits in percent-encoded octets, e.g. `%e5` → `%E5`.
+///
+/// Malformed escapes like `%zz` are kept as is.
+fn uppercase_percent_encoding(url: &str) -> String {
+    let mut result = String::with_capacity(url.len());
+    let mut rest = url;
+    while let Some(i) = rest.find('%') {
+        result.push_str(&rest[..=i]);
+        rest = &rest[i + 1..];
+        if let Some(octet) = rest.get(..2)
+            && octet.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            result.push_str(&octet.to_ascii_uppercase());
+            rest = &rest[2..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scheme() {
+        assert_eq!(
+            canonicalize("http://example.com/a").as_deref(),
+            Some("https://example.com/a")
+        );
+        assert_eq!(canonicalize("ftp://example.com/a"), None);
+        assert_eq!(canonicalize("/typechat/ep-001/"), None);
+    }
+
+    #[test]
+    fn trailing_slash() {
+        assert_eq!(
+            canonicalize("https://www.thetype.com/typechat/ep-001/").as_deref(),
+            Some("https://www.thetype.com/typechat/ep-001")
+        );
+        assert_eq!(
+            canonicalize("https://example.com/").as_deref(),
+            Some("https://example.com/")
+        );
+        // Only a single slash is removed, and `//` is a path other than the root
+        assert_eq!(
+            canonicalize("https://a.com//").as_deref(),
+            Some("https://a.com//")
+        );
+        assert_eq!(
+            canonicalize("https://a.com/b//").as_deref(),
+            Some("https://a.com/b/")
+        );
+    }
+
+    #[test]
+    fn non_ascii() {
+        assert_eq!(
+            canonicalize("https://zh.wikipedia.org/wiki/字体"),
+            canonicalize("https://zh.wikipedia.org/wiki/%e5%ad%97%e4%bd%93"),
+        );
+        assert_eq!(
+            canonicalize("https://zh.wikipedia.org/wiki/字体").as_deref(),
+            Some("https://zh.wikipedia.org/wiki/%E5%AD%97%E4%BD%93")
+        );
+        // Malformed escapes are not changed
+        assert_eq!(
+            canonicalize("https://a.com/%zz%e5%a").as_deref(),
+            Some("https://a.com/%zz%E5%a")
+        );
+    }
+
+    #[test]
+    fn tracking_params() {
+        assert_eq!(
+            canonicalize("https://example.com/a?utm_source=tw&fbclid=x").as_deref(),
+            Some("https://example.com/a")
+        );
+        assert_eq!(
+            canonicalize("https://example.com/a?").as_deref(),
+            Some("https://example.com/a")
+        );
+        // The rest of the query is kept as is
+        assert_eq!(
+            canonicalize("https://a.com/search?q=a b&x=c/d&utm_source=tw"),
+            canonicalize("https://a.com/search?q=a b&x=c/d"),
+        );
+        assert_eq!(
+            canonicalize("https://a.com/search?spm=1&q=a%20b&x=c/d").as_deref(),
+            Some("https://a.com/search?q=a%20b&x=c/d")
+        );
+    }
+
+    #[test]
+    fn fragment() {
+        assert_eq!(
+            canonicalize("https://example.com/a#section").as_deref(),
+            Some("https://example.com/a")
+        );
+        assert_eq!(
+            canonicalize("https://example.com/#/route").as_deref(),
+            Some("https://example.com/#/route")
+        );
+        assert_eq!(
+            canonicalize("https://example.com/#!/route").as_deref(),
+            Some("https://example.com/#!/route")
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Compare two versions of the data, e.g. the last release bundle and the current caches.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+};
+
+use serde::Serialize;
+
+use crate::data::{Episode, Link, LinkChanges};
+
+/// Changes between two versions of episodes.
+#[derive(Debug, Serialize)]
+pub struct Changelog {
+    pub new_episodes: Vec<EpisodeSummary>,
+    pub removed_episodes: Vec<EpisodeSummary>,
+    /// Known episodes whose links changed
+    pub changed_episodes: Vec<ChangedEpisode>,
+    /// Domains entering the top of statistics, with their new counts
+    pub domains_entering: Vec<(String, i32)>,
+    /// Domains leaving the top of statistics, with their old counts
+    pub domains_leaving: Vec<(String, i32)>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EpisodeSummary {
+    /// e.g. `ep-250`
+    pub id: String,
+    pub name: String,
+    pub url: String,
+    /// Number of distinct links
+    pub links: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangedEpisode {
+    #[serde(flatten)]
+    pub episode: EpisodeSummary,
+    pub added: BTreeSet<String>,
+    pub removed: BTreeSet<String>,
+}
+
+impl EpisodeSummary {
+    fn new(episode: &Episode, links: &[Link]) -> Self {
+        Self {
+            id: episode.id().to_string(),
+            name: episode.name.to_owned(),
+            url: episode.url.to_owned(),
+            links: links.iter().map(Link::key).collect::<BTreeSet<_>>().len(),
+        }
+    }
+}
+
+impl Changelog {
+    /// Compare episodes, and the top domains (as ranked by statistics) of each version.
+    pub fn between(
+        old: &HashMap<Episode, Vec<Link>>,
+        new: &HashMap<Episode, Vec<Link>>,
+        old_top: &[(&str, i32)],
+        new_top: &[(&str, i32)],
+    ) -> Self {
+        let mut new_episodes = Vec::new();
+        let mut changed_episodes = Vec::new();
+        for (episode, links) in sorted(new) {
+            match old.get(episode) {
+                None => new_episodes.push(EpisodeSummary::new(episode, links)),
+                Some(old_links) => {
+                    let LinkChanges { added, removed } = LinkChanges::between(old_links, links);
+                    if !added.is_empty() || !removed.is_empty() {
+                        changed_episodes.push(ChangedEpisode {
+                            episode: EpisodeSummary::new(episode, links),
+                            added,
+                            removed,
+                        });
+                    }
+                }
+            }
+        }
+
+        let removed_episodes = sorted(old)
+            .into_iter()
+            .filter(|(episode, _)| !new.contains_key(episode))
+            .map(|(episode, links)| EpisodeSummary::new(episode, links))
+            .collect();
+
+        Self {
+            new_episodes,
+            removed_episodes,
+            changed_episodes,
+            domains_entering: difference(new_top, old_top),
+            domains_leaving: difference(old_top, new_top),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.new_episodes.is_empty()
+            && self.removed_episodes.is_empty()
+            && self.changed_episodes.is_empty()
+            && self.domains_entering.is_empty()
+            && self.domains_leaving.is_empty()
+    }
+
+    /// Write a markdown report.
+    pub fn write_markdown(&self, mut buffer: impl io::Write) -> io::Result<()> {
+        buffer.write_all(b"# Changelog\n")?;
+        if self.is_empty() {
+            buffer.write_all(b"\nNothing changed.\n")?;
+            return Ok(());
+        }
+
+        for (heading, episodes) in [
+            ("New episodes", &self.new_episodes),
+            ("Removed episodes", &self.removed_episodes),
+        ] {
+            if !episodes.is_empty() {
+                writeln!(buffer, "\n## {heading}\n")?;
+                for ep in episodes {
+                    writeln!(buffer, "- [{}]({}) ({} links)", ep.name, ep.url, ep.links)?;
+                }
+            }
+        }
+
+        if !self.changed_episodes.is_empty() {
+            buffer.write_all(b"\n## Changed links\n")?;
+            for changed in &self.changed_episodes {
+                let ep = &changed.episode;
+                writeln!(buffer, "\n### [{}]({})\n", ep.name, ep.url)?;
+                for url in &changed.added {
+                    writeln!(buffer, "- Added <{url}>")?;
+                }
+                for url in &changed.removed {
+                    writeln!(buffer, "- Removed <{url}>")?;
+                }
+            }
+        }
+
+        for (heading, domains) in [
+            ("Domains entering the top", &self.domains_entering),
+            ("Domains leaving the top", &self.domains_leaving),
+        ] {
+            if !domains.is_empty() {
+                writeln!(buffer, "\n## {heading}\n")?;
+                for (domain, count) in domains {
+                    writeln!(buffer, "- {domain} ({count} references)")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn sorted(episodes: &HashMap<Episode, Vec<Link>>) -> Vec<(&Episode, &Vec<Link>)> {
+    let mut episodes: Vec<_> = episodes.iter().collect();
+    episodes.sort_by_cached_key(|(ep, _)| ep.id());
+    episodes
+}
+
+/// Domains in `a` but not in `b`, keeping counts in `a`.
+fn difference(a: &[(&str, i32)], b: &[(&str, i32)]) -> Vec<(String, i32)> {
+    a.iter()
+        .filter(|(domain, _)| !b.iter().any(|(d, _)| d == domain))
+        .map(|&(domain, count)| (domain.to_owned(), count))
+        .collect()
+}
@@ -0,0 +1,68 @@
+//! Command line interface.
+
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+/// Collect reference links in TypeChat show notes, and summarize them.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Never access the network; use the data caches only
+    #[arg(long, global = true)]
+    pub offline: bool,
+    /// Stop fetching at a page consisting of cached and unchanged episodes only (overrides the config file)
+    #[arg(long, global = true)]
+    pub incremental: bool,
+    /// Config file [default: typechat-link.toml, if exists]
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Directory of data caches (overrides the config file)
+    #[arg(long, global = true)]
+    pub data_dir: Option<PathBuf>,
+    /// Directory of output files (overrides the config file)
+    #[arg(long, global = true)]
+    pub out_dir: Option<PathBuf>,
+    /// Omit domains with less references in statistics (overrides the config file)
+    #[arg(long, global = true)]
+    pub min_link_ref: Option<i32>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Fetch episodes from the Internet and update the data caches.
+    Fetch,
+    /// Save statistics of external links, using the data caches only.
+    Stats,
+    /// Paint in-TypeChat links, using the data caches only.
+    Paint,
+    /// Fetch episodes, then save statistics and paint (default).
+    ///
+    /// With `--offline`, skip fetching.
+    All,
+    /// Extract links again from stored show notes, and update the data caches.
+    ///
+    /// Never access the network, even for short URLs that are not cached.
+    Reextract,
+    /// Search links in the data caches.
+    Query {
+        /// Substring to search for
+        pattern: String,
+        /// Match episodes’ IDs (e.g. `250` or `ep-250`), names and URLs instead of links, and list all their links
+        #[arg(short, long)]
+        episode: bool,
+    },
+    /// Compare two versions of the episodes cache, and save a changelog.
+    Diff {
+        /// The old episodes cache [default: the backup of the current one]
+        old: Option<PathBuf>,
+        /// The new episodes cache [default: the current one]
+        new: Option<PathBuf>,
+        /// Save JSON instead of markdown
+        #[arg(long)]
+        json: bool,
+    },
+}
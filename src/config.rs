@@ -0,0 +1,327 @@
+//! Load configuration from a TOML file.
+//!
+//! Every field has a default, so the file and each of its tables are optional.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use random_color::options::Luminosity;
+use reqwest::blocking::Client;
+use serde::Deserialize;
+
+use crate::{
+    data::{DEFAULT_SHORTENERS, ExpansionRetryPolicy, LinkRole, RetryPolicy},
+    http::{HttpClient, RateLimiter, WorkerPool},
+};
+
+/// The config file to use if `--config` is not given.
+pub const DEFAULT_CONFIG: &str = "typechat-link.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub paths: PathsConfig,
+    pub stats: StatsConfig,
+    pub fetch: FetchConfig,
+    pub cache: CacheConfig,
+    pub http: HttpConfig,
+    pub shorteners: ShortenersConfig,
+    pub rate_limit: RateLimitConfig,
+    pub graph: GraphConfig,
+}
+
+impl Config {
+    /// Load the config file at `path`.
+    ///
+    /// If `path` is `None`, try [`DEFAULT_CONFIG`], and fall back to the default config if it does not exist.
+    pub fn load(path: Option<&Path>) -> io::Result<Self> {
+        let content = match path {
+            Some(path) => {
+                println!("Loading config from {}…", path.display());
+                fs::read_to_string(path)?
+            }
+            None => match fs::read_to_string(DEFAULT_CONFIG) {
+                Ok(content) => {
+                    println!("Loading config from {DEFAULT_CONFIG}…");
+                    content
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+                Err(err) => return Err(err),
+            },
+        };
+
+        let config: Self = toml::from_str(&content)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        config.rate_limit.validate()?;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Directory of data caches
+    pub data_dir: PathBuf,
+    /// Directory of output files
+    pub out_dir: PathBuf,
+    /// SQLite database to write along with the data caches (requires the `sqlite` feature)
+    pub database: Option<PathBuf>,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: "data".into(),
+            out_dir: "out".into(),
+            database: None,
+        }
+    }
+}
+
+impl PathsConfig {
+    pub fn episodes(&self) -> PathBuf {
+        self.data_dir.join("episodes.json")
+    }
+    pub fn short_urls(&self) -> PathBuf {
+        self.data_dir.join("short_urls.json")
+    }
+    pub fn show_notes(&self) -> PathBuf {
+        self.data_dir.join("show_notes")
+    }
+    pub fn paint(&self) -> PathBuf {
+        self.out_dir.join("typechat.dot")
+    }
+    pub fn stats(&self) -> PathBuf {
+        self.out_dir.join("external-links.md")
+    }
+    pub fn short_urls_report(&self) -> PathBuf {
+        self.out_dir.join("short-urls.md")
+    }
+    pub fn diagnostics(&self) -> PathBuf {
+        self.out_dir.join("diagnostics.md")
+    }
+    pub fn changelog(&self, json: bool) -> PathBuf {
+        self.out_dir.join(if json {
+            "changelog.json"
+        } else {
+            "changelog.md"
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsConfig {
+    /// Domains with less references are omitted.
+    pub min_link_ref: i32,
+    /// Roles of links to count
+    pub roles: Vec<LinkRole>,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            min_link_ref: 11,
+            roles: vec![LinkRole::Reference],
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FetchConfig {
+    /// Stop fetching at a page consisting of cached and unchanged episodes only
+    pub incremental: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Indent data caches, to make diffs readable
+    pub pretty: bool,
+    /// Keep the raw HTML of show notes
+    pub show_notes: bool,
+    /// Compress show notes with gzip
+    pub compress_show_notes: bool,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            pretty: false,
+            show_notes: true,
+            compress_show_notes: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Timeout of a whole request, in seconds
+    pub timeout_secs: u64,
+    /// Timeout of connecting, in seconds
+    pub connect_timeout_secs: u64,
+    /// Maximum number of retries after a failed request
+    pub retries: u32,
+    /// Delay before the first retry, in milliseconds, doubled for each further retry
+    pub backoff_ms: u64,
+    /// Maximum number of concurrent requests when expanding short URLs
+    pub workers: usize,
+    /// Maximum number of concurrent requests to the same host
+    pub per_host: usize,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: 30,
+            connect_timeout_secs: 10,
+            retries: 3,
+            backoff_ms: 1000,
+            workers: 8,
+            per_host: 2,
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn build_client(&self, limiter: RateLimiter) -> reqwest::Result<HttpClient> {
+        HttpClient::build(
+            || {
+                Client::builder()
+                    .timeout(Duration::from_secs(self.timeout_secs))
+                    .connect_timeout(Duration::from_secs(self.connect_timeout_secs))
+            },
+            limiter,
+        )
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            retries: self.retries,
+            backoff: Duration::from_millis(self.backoff_ms),
+        }
+    }
+
+    pub fn worker_pool(&self) -> WorkerPool {
+        WorkerPool {
+            workers: self.workers,
+            per_host: self.per_host,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShortenersConfig {
+    /// Hosts of URL shorteners, whose links are expanded to the real destinations
+    pub hosts: Vec<String>,
+    /// Expand a failed URL again after this number of hours
+    pub retry_after_hours: u64,
+    /// Stop retrying after this number of failed attempts
+    pub max_attempts: u32,
+}
+
+impl Default for ShortenersConfig {
+    fn default() -> Self {
+        let retry = ExpansionRetryPolicy::default();
+        Self {
+            hosts: DEFAULT_SHORTENERS.iter().map(|&h| h.to_owned()).collect(),
+            retry_after_hours: retry.retry_after.as_secs() / 3600,
+            max_attempts: retry.max_attempts,
+        }
+    }
+}
+
+impl ShortenersConfig {
+    pub fn retry_policy(&self) -> ExpansionRetryPolicy {
+        ExpansionRetryPolicy {
+            retry_after: Duration::from_secs(self.retry_after_hours * 3600),
+            max_attempts: self.max_attempts,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Maximum number of requests per second, to each host
+    pub requests_per_second: f64,
+    /// Maximum number of requests at once, to each host
+    pub burst: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 1.0,
+            burst: 1,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Reject rates that would make waiting time infinite or negative.
+    fn validate(&self) -> io::Result<()> {
+        if self.requests_per_second.is_finite() && self.requests_per_second > 0.0 {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "rate_limit.requests_per_second should be a positive number, got {}",
+                    self.requests_per_second
+                ),
+            ))
+        }
+    }
+
+    pub fn build_limiter(&self) -> RateLimiter {
+        RateLimiter::new(self.requests_per_second, self.burst)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GraphConfig {
+    /// Graphviz `rankdir`, e.g. `LR` or `TB`
+    pub rankdir: String,
+    /// Luminosity of edges’ random colors
+    pub edge_luminosity: EdgeLuminosity,
+    /// Roles of links to paint
+    pub roles: Vec<LinkRole>,
+}
+
+impl Default for GraphConfig {
+    fn default() -> Self {
+        Self {
+            rankdir: "LR".to_owned(),
+            edge_luminosity: EdgeLuminosity::Light,
+            roles: vec![LinkRole::Reference],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeLuminosity {
+    Random,
+    Bright,
+    Light,
+    Dark,
+}
+
+impl From<EdgeLuminosity> for Luminosity {
+    fn from(value: EdgeLuminosity) -> Self {
+        match value {
+            EdgeLuminosity::Random => Luminosity::Random,
+            EdgeLuminosity::Bright => Luminosity::Bright,
+            EdgeLuminosity::Light => Luminosity::Light,
+            EdgeLuminosity::Dark => Luminosity::Dark,
+        }
+    }
+}
@@ -3,17 +3,30 @@
 //! [`Fetcher`] fetches episodes and show notes, and [`Driver`] collect them into links.
 
 use std::{
-    collections::{HashMap, hash_map::Entry},
-    io, thread, time,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt, io, thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use reqwest::blocking::Client;
-use scraper::{Html, Selector};
-use serde::{Deserialize, Serialize};
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Deserializer, Serialize, de};
+use sha2::{Digest, Sha256};
 use std::hash::{Hash, Hasher};
 
+use crate::{
+    cache,
+    canonical::canonicalize,
+    diagnostics::{Diagnostic, DiagnosticKind, Diagnostics},
+    episode::{EpisodeId, Title},
+    http::{HttpClient, WorkerPool},
+    show_notes::ShowNotesStore,
+};
+
+#[cfg(feature = "sqlite")]
+use crate::sqlite::Database;
+
 /// A fetcher that fetches episodes and show notes from WordPress API lazily.
-#[derive(Default)]
 pub struct Fetcher {
     pages_fetcher: PagesFetcher,
     /// Total number of episodes.
@@ -21,40 +34,101 @@ pub struct Fetcher {
 }
 
 impl Fetcher {
-    pub fn build() -> reqwest::Result<Self> {
-        let pages_fetcher = PagesFetcher::default();
-        let len = pages_fetcher.load_first_page()?.total_posts;
+    pub fn build(client: HttpClient, retry: RetryPolicy) -> reqwest::Result<Self> {
+        let mut pages_fetcher = PagesFetcher::new(client, retry);
+        let first_page = pages_fetcher.load_first_page()?;
+        let len = first_page.total_posts;
+        pages_fetcher.total_pages = first_page.total_pages;
+        pages_fetcher.total_posts = first_page.total_posts;
+        // Keep it to avoid fetching it again
+        pages_fetcher.first_page = Some(first_page);
         Ok(Self { pages_fetcher, len })
     }
+
+    /// Stop fetching after a page consisting of known and unchanged episodes only.
+    ///
+    /// The API returns newest episodes first, so the following pages should be known as well.
+    /// However, if fewer episodes are known than the total, e.g. after an interrupted or incomplete fetch,
+    /// older pages have gaps, and fetching goes on.
+    /// `known` maps URLs to episodes.
+    pub fn incremental(mut self, known: HashMap<String, Episode>) -> Self {
+        self.pages_fetcher.known = Some(known);
+        self
+    }
+
     /// Get total number of episodes.
     pub fn len(&self) -> usize {
         self.len
     }
 
+    /// Pages that could not be fetched so far.
+    pub fn failed_pages(&self) -> &[(usize, String)] {
+        &self.pages_fetcher.failed_pages
+    }
+
     /// Iterate over episodes and their show notes.
+    ///
+    /// Pages that could not be fetched are skipped, and recorded in [`Fetcher::failed_pages`].
     pub fn iter(&mut self) -> impl Iterator<Item = (Episode, String)> + '_ {
         self.pages_fetcher
             .by_ref()
-            .flat_map(|page| page.ok()) // Ignore network errors
-            .flat_map(|page| {
-                page.posts.into_iter().map(|post| {
-                    (
-                        Episode {
-                            name: post.post_title,
-                            url: post.link,
-                        },
-                        post.post_content,
-                    )
-                })
-            })
+            .flat_map(|page| page.ok()) // Errors are recorded in `failed_pages`
+            .flat_map(|page| page.posts.into_iter().map(WpPost::into_episode))
+    }
+}
+
+/// How to retry failed requests.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Maximum number of retries after the first attempt
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each further retry
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// The delay before the `n`-th retry, counting from 0.
+    fn delay(&self, n: u32) -> Duration {
+        self.backoff.saturating_mul(2_u32.saturating_pow(n))
     }
 }
 
+/// Summary of an incomplete fetch.
+#[derive(Debug)]
+pub struct IncompleteFetch {
+    /// Numbers of pages that could not be fetched
+    pub failed_pages: Vec<usize>,
+}
+
+impl fmt::Display for IncompleteFetch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the fetch is incomplete: failed to fetch {} pages ({:?})",
+            self.failed_pages.len(),
+            self.failed_pages
+        )
+    }
+}
+
+impl std::error::Error for IncompleteFetch {}
+
 /// A fetcher that fetches pages from WordPress API lazily.
 struct PagesFetcher {
-    client: Client,
+    client: HttpClient,
+    retry: RetryPolicy,
     /// The next page number to fetch.
     next_page: Option<usize>,
+    /// Total number of pages, as reported by the first page
+    total_pages: usize,
+    /// Total number of posts, as reported by the first page
+    total_posts: usize,
+    /// Pages that could not be fetched, and the last error
+    failed_pages: Vec<(usize, String)>,
+    /// The first page, if loaded in advance
+    first_page: Option<WpPostsPage>,
+    /// Known episodes by URL, if fetching incrementally
+    known: Option<HashMap<String, Episode>>,
 }
 /// A page of posts in WordPress API.
 #[derive(Debug, Deserialize)]
@@ -77,12 +151,143 @@ struct WpPost {
     ///
     /// `summary`, and `post_excerpt` are also available, but they are truncated.
     post_content: String,
+    /// Post ID
+    #[serde(rename = "ID", alias = "id", default)]
+    post_id: Option<u64>,
+    /// Example: `2024-09-19 16:00:00`, in the site’s time zone
+    #[serde(default)]
+    post_date: Option<String>,
+    /// Example: `2024-09-19 08:00:00`, in UTC, or `0000-00-00 00:00:00` if unknown
+    #[serde(default)]
+    post_date_gmt: Option<String>,
+    #[serde(default)]
+    post_modified: Option<String>,
+    #[serde(default)]
+    post_modified_gmt: Option<String>,
+    /// URL of the audio enclosure
+    #[serde(alias = "enclosure", alias = "audio_file", default)]
+    audio: Option<String>,
+    /// Example: `01:23:45`
+    #[serde(default)]
+    duration: Option<String>,
+    /// Names, IDs, or term objects
+    #[serde(default)]
+    categories: Vec<serde_json::Value>,
+    /// Names, IDs, or term objects
+    #[serde(default)]
+    tags: Vec<serde_json::Value>,
+}
+
+impl WpPost {
+    /// Split into an episode and its show notes.
+    fn into_episode(self) -> (Episode, String) {
+        (
+            Episode {
+                name: self.post_title,
+                url: self.link,
+                post_id: self.post_id,
+                date: wp_date(self.post_date_gmt, self.post_date),
+                modified: wp_date(self.post_modified_gmt, self.post_modified),
+                audio_url: self.audio.filter(|url| !url.is_empty()),
+                duration: self.duration.filter(|d| !d.is_empty()),
+                // Filled in by `Driver::push_episode`
+                content_hash: None,
+                categories: wp_terms(self.categories),
+                tags: wp_terms(self.tags),
+            },
+            self.post_content,
+        )
+    }
+
+    /// Check if the post is the same as a known episode.
+    ///
+    /// Compare the content hash, or the modified date if the hash is not recorded.
+    fn is_unchanged(&self, known: &Episode) -> bool {
+        match &known.content_hash {
+            Some(hash) => *hash == content_hash(&self.post_content),
+            None => {
+                known.modified.is_some()
+                    && known.modified
+                        == wp_date(self.post_modified_gmt.clone(), self.post_modified.clone())
+            }
+        }
+    }
+}
+
+/// Hash show notes to detect edits.
+fn content_hash(show_notes: &str) -> String {
+    Sha256::digest(show_notes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Convert a WordPress date to ISO 8601, preferring the UTC one.
+///
+/// Example: `2024-09-19 08:00:00` (UTC) → `2024-09-19T08:00:00Z`
+fn wp_date(gmt: Option<String>, local: Option<String>) -> Option<String> {
+    let valid = |date: &String| !date.is_empty() && !date.starts_with("0000");
+    match (gmt.filter(valid), local.filter(valid)) {
+        (Some(gmt), _) => Some(format!("{}Z", gmt.replace(' ', "T"))),
+        (None, Some(local)) => Some(local.replace(' ', "T")),
+        (None, None) => None,
+    }
+}
+
+/// Extract names of WordPress terms (categories or tags).
+fn wp_terms(terms: Vec<serde_json::Value>) -> Vec<String> {
+    terms
+        .into_iter()
+        .filter_map(|term| match term {
+            serde_json::Value::String(name) => Some(name),
+            serde_json::Value::Number(id) => Some(id.to_string()),
+            serde_json::Value::Object(mut map) => match map.remove("name") {
+                Some(serde_json::Value::String(name)) => Some(name),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
 }
 
 impl PagesFetcher {
+    fn new(client: HttpClient, retry: RetryPolicy) -> Self {
+        Self {
+            client,
+            retry,
+            next_page: Some(1), // It starts from 1, not 0.
+            total_pages: 1,
+            total_posts: 0,
+            failed_pages: Vec::new(),
+            first_page: None,
+            known: None,
+        }
+    }
+
+    /// Load a page, retrying with exponential backoff.
     fn load_page(&self, page: usize) -> reqwest::Result<WpPostsPage> {
+        let mut n_retries = 0;
+        loop {
+            match self.try_load_page(page) {
+                Err(err) if n_retries < self.retry.retries => {
+                    let delay = self.retry.delay(n_retries);
+                    n_retries += 1;
+                    eprintln!(
+                        "⚠️ Failed to fetch page {page}: {err}. Retry in {:.1} seconds ({n_retries}/{})…",
+                        delay.as_secs_f64(),
+                        self.retry.retries
+                    );
+                    thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// [`load_page`] without retrying
+    fn try_load_page(&self, page: usize) -> reqwest::Result<WpPostsPage> {
         let url = format!("https://www.thetype.com/wp-json/wp/v2/get-typechat-posts?page={page}",);
-        let page_content: WpPostsPage = self.client.get(&url).send()?.json()?;
+        let page_content: WpPostsPage = self.client.get(&url)?.error_for_status()?.json()?;
         assert_eq!(page_content.current_page, page);
 
         Ok(page_content)
@@ -90,15 +295,37 @@ impl PagesFetcher {
 
     fn fetch_next_page(&mut self) -> reqwest::Result<Option<WpPostsPage>> {
         if let Some(page) = self.next_page {
-            let page_content = self.load_page(page)?;
-
+            // Move on before loading, so that a failed page is not retried forever
             let next_page = page + 1;
-            self.next_page = if next_page <= page_content.total_pages {
+            self.next_page = if next_page <= self.total_pages {
                 Some(next_page)
             } else {
                 None
             };
 
+            let page_content = match self.first_page.take() {
+                Some(first_page) if first_page.current_page == page => first_page,
+                _ => self.load_page(page).inspect_err(|err| {
+                    eprintln!("❌ Failed to fetch page {page}: {err}.");
+                    self.failed_pages.push((page, err.to_string()));
+                })?,
+            };
+
+            if let Some(known) = &self.known
+                && self.next_page.is_some()
+                && known.len() >= self.total_posts
+                && page_content.posts.iter().all(|post| {
+                    known
+                        .get(&post.link)
+                        .is_some_and(|episode| post.is_unchanged(episode))
+                })
+            {
+                println!(
+                    "⏩ Episodes in page {page} are all known and unchanged. Skip the rest pages."
+                );
+                self.next_page = None;
+            }
+
             Ok(Some(page_content))
         } else {
             Ok(None)
@@ -110,15 +337,6 @@ impl PagesFetcher {
     }
 }
 
-impl Default for PagesFetcher {
-    fn default() -> Self {
-        Self {
-            client: Client::new(),
-            next_page: Some(1), // It starts from 1, not 0.
-        }
-    }
-}
-
 impl Iterator for PagesFetcher {
     type Item = reqwest::Result<WpPostsPage>;
 
@@ -127,20 +345,90 @@ impl Iterator for PagesFetcher {
     }
 }
 
+/// Hosts of URL shorteners to expand by default.
+pub const DEFAULT_SHORTENERS: &[&str] = &[
+    "t.cn", "bit.ly", "goo.gl", "adobe.ly", "youtu.be", "b23.tv", "dwz.cn",
+];
+
+/// An episode with its links, as stored in the episodes cache.
+#[derive(Serialize, Deserialize)]
+struct CachedEpisode<E, L> {
+    #[serde(flatten)]
+    episode: E,
+    links: L,
+}
+
 /// A driver that collects episode show notes into a map of links.
 pub struct Driver {
     /// Links in episodes’ show notes
-    pub episodes: HashMap<Episode, Vec<String>>,
+    pub episodes: HashMap<Episode, Vec<Link>>,
     /// Short URL cache
     short_urls: ShortcutUrlCache,
+    /// Anomalies found in show notes pushed so far
+    pub diagnostics: Diagnostics,
+    /// Hosts of URL shorteners
+    shorteners: HashSet<String>,
+    /// When to retry failed expansions of shortcut URLs
+    expansion_retry: ExpansionRetryPolicy,
     // HTTP client
-    client: Client,
+    client: HttpClient,
+    /// Where to keep the raw HTML of show notes
+    show_notes: Option<ShowNotesStore>,
+    /// Database to write along with the caches
+    #[cfg(feature = "sqlite")]
+    database: Option<Database>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Eq, Clone)]
 pub struct Episode {
     pub name: String,
     pub url: String,
+    /// Post ID in WordPress
+    #[serde(default)]
+    pub post_id: Option<u64>,
+    /// Publication date in ISO 8601, e.g. `2024-09-19T08:00:00Z`
+    #[serde(default)]
+    pub date: Option<String>,
+    /// Last modified date in ISO 8601
+    #[serde(default)]
+    pub modified: Option<String>,
+    /// URL of the audio
+    #[serde(default)]
+    pub audio_url: Option<String>,
+    /// Duration of the audio, e.g. `01:23:45`
+    #[serde(default)]
+    pub duration: Option<String>,
+    /// SHA-256 of the show notes’ HTML, in hex
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    pub categories: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl Episode {
+    /// Identify the episode by its URL, or by its title if the URL is irregular.
+    pub fn id(&self) -> EpisodeId {
+        match EpisodeId::from_url(&self.url) {
+            Some(id @ (EpisodeId::Numbered(_) | EpisodeId::Bonus { .. })) => id,
+            from_url => EpisodeId::from_title(&self.name)
+                .or(from_url)
+                .unwrap_or_else(|| EpisodeId::Special(self.slug().unwrap_or_default())),
+        }
+    }
+
+    /// The last segment of the URL’s path, e.g. `ep-250`.
+    ///
+    /// Unlike [`Episode::id`], it never collides between episodes.
+    pub fn slug(&self) -> Option<String> {
+        let url = Url::parse(&self.url).ok()?;
+        let slug = url.path_segments()?.rfind(|s| !s.is_empty())?;
+        Some(slug.to_owned())
+    }
+
+    /// Parse the title.
+    pub fn title(&self) -> Title<'_> {
+        Title::parse(&self.name)
+    }
 }
 
 impl PartialEq for Episode {
@@ -155,37 +443,469 @@ impl Hash for Episode {
     }
 }
 
+/// A link in an episode’s show notes.
+///
+/// Legacy forms are converted by [`crate::cache`] migrations, so deserialization is strict.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Link {
+    /// `href` as written in the show notes
+    pub href: String,
+    /// `href`, with shortcut URLs expanded
+    pub url: String,
+    /// Canonical form of `url`, if it is an absolute HTTP(S) URL
+    pub canonical: Option<String>,
+    /// Text of the anchor, with whitespace collapsed
+    pub text: String,
+    /// Position among links in the show notes, counting from 0
+    pub index: usize,
+    /// Text of the enclosing block, e.g. the paragraph or the list item
+    pub context: Option<String>,
+    /// Text of the nearest heading before the link
+    pub section: Option<String>,
+    /// Role of the link, judged by its position in the document
+    pub role: LinkRole,
+}
+
+/// Changes of links in an episode, compared by [`Link::key`].
+#[derive(Debug, Default)]
+pub struct LinkChanges {
+    pub added: BTreeSet<String>,
+    pub removed: BTreeSet<String>,
+}
+
+impl LinkChanges {
+    pub fn between(old: &[Link], new: &[Link]) -> Self {
+        let old: BTreeSet<_> = old.iter().map(Link::key).collect();
+        let new: BTreeSet<_> = new.iter().map(Link::key).collect();
+        Self {
+            added: new.difference(&old).map(|&k| k.to_owned()).collect(),
+            removed: old.difference(&new).map(|&k| k.to_owned()).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// The result of [`Driver::reextract`].
+#[derive(Debug, Default)]
+pub struct Reextraction {
+    /// Episodes whose links changed, sorted by [`EpisodeId`]
+    ///
+    /// Changes might be empty, if only texts, positions, or roles of links changed.
+    pub changed: Vec<(Episode, LinkChanges)>,
+    /// Episodes whose show notes are not stored
+    pub missing: Vec<Episode>,
+}
+
+/// Role of a link in the show notes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkRole {
+    /// A reference in the content
+    #[default]
+    Reference,
+    /// The subscription block in the footer, e.g. feeds, podcast platforms, and audio downloads
+    Subscription,
+    /// Embedded media, e.g. audio and video players
+    Media,
+}
+
+/// Containers of the subscription block
+///
+/// Example: Footer of https://www.thetype.com/typechat/ep-001/
+const SUBSCRIPTION_SELECTOR: &str = "p.noindent, .typechat-subscribe, .podcast-subscribe";
+/// Containers of embedded media
+const MEDIA_SELECTOR: &str = "audio, video, object, embed, iframe, .wp-audio-shortcode, .wp-video-shortcode, .wp-block-audio, .wp-block-video, .wp-block-embed, .mejs-container";
+
+impl LinkRole {
+    /// Judge the role of an anchor by its ancestors.
+    fn classify(a: ElementRef, subscription: &Selector, media: &Selector) -> Self {
+        let mut ancestors = a.ancestors().filter_map(ElementRef::wrap);
+        match ancestors.find(|e| subscription.matches(e) || media.matches(e)) {
+            Some(e) if media.matches(&e) => Self::Media,
+            Some(_) => Self::Subscription,
+            None => Self::Reference,
+        }
+    }
+
+    /// Guess the role by the URL, for links extracted before roles were recorded.
+    pub fn guess(link: &Link) -> Self {
+        let url = link.key();
+        let is_subscription = matches!(
+            url,
+            // 各平台《字谈字畅》
+            "https://www.thetype.com/typechat/feed"
+                | "https://www.lizhi.fm/1852153"
+                | "https://music.163.com/#/djradio?id=346541057"
+                | "https://www.thetype.com/feed/typechat"
+                | "https://itunes.apple.com/cn/podcast/zi-tan-zi-chang/id1041704528"
+                | "https://podcasts.apple.com/cn/podcast/%E5%AD%97%E8%B0%88%E5%AD%97%E7%95%85/id1041704528"
+                | "https://itunes.apple.com/cn/podcast/%E5%AD%97%E8%B0%88%E5%AD%97%E7%95%85/id1041704528"
+                | "https://static.thetype.cloud/typechat/assets/typechat-weapp.jpg"
+        ) || (url.starts_with("https://static.thetype.cloud/typechat/")
+            && url.ends_with(".mp3"));
+
+        if is_subscription {
+            Self::Subscription
+        } else {
+            Self::Reference
+        }
+    }
+}
+
+impl Link {
+    /// A link with nothing but `href`.
+    pub fn new(href: String) -> Self {
+        Self {
+            canonical: canonicalize(&href),
+            url: href.to_owned(),
+            href,
+            text: String::new(),
+            index: 0,
+            context: None,
+            section: None,
+            role: LinkRole::Reference,
+        }
+    }
+
+    /// Replace `url` with the expanded URL, and canonicalize it again.
+    fn set_url(&mut self, url: String) {
+        self.canonical = canonicalize(&url);
+        self.url = url;
+    }
+
+    /// The canonical form if available, or `url` otherwise.
+    ///
+    /// Use this to compare and count links.
+    pub fn key(&self) -> &str {
+        self.canonical.as_deref().unwrap_or(&self.url)
+    }
+}
+
+/// Elements that can enclose a link as its context
+const BLOCK_ELEMENTS: &[&str] = &[
+    "p",
+    "li",
+    "dt",
+    "dd",
+    "blockquote",
+    "figcaption",
+    "td",
+    "th",
+    "pre",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+];
+
+/// Collect the text in an element, with whitespace collapsed.
+fn collapsed_text(element: ElementRef) -> String {
+    element
+        .text()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Maximum number of redirects to follow when expanding a shortcut URL
+const MAX_REDIRECTS: usize = 10;
+
+/// Sorted by shortcut URLs, to be serialized in a stable order
 #[derive(Debug, Serialize, Deserialize)]
-struct ShortcutUrlCache(HashMap<String, String>);
+struct ShortcutUrlCache(BTreeMap<String, Expansion>);
+
+/// The result of expanding a shortcut URL.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Expansion {
+    /// Failed, and the shortcut URL is kept as the link
+    Failed {
+        failure: ExpansionFailure,
+    },
+    Resolved(RedirectChain),
+}
+
+impl<'de> Deserialize<'de> for Expansion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Failed {
+            failure: ExpansionFailure,
+        }
+
+        // Tell variants apart by the `failure` field, so that errors point to the right one
+        let value = serde_json::Value::deserialize(deserializer)?;
+        if value.get("failure").is_some() {
+            serde_json::from_value(value).map(|Failed { failure }| Self::Failed { failure })
+        } else {
+            serde_json::from_value(value).map(Self::Resolved)
+        }
+        .map_err(de::Error::custom)
+    }
+}
+
+/// A failed expansion, cached to avoid requesting a dead shortener again and again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpansionFailure {
+    pub kind: FailureKind,
+    pub message: String,
+    /// When it failed most recently, in seconds since the Unix epoch
+    pub failed_at: u64,
+    /// Number of failed attempts so far
+    pub attempts: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    Timeout,
+    Connect,
+    Status,
+    Redirect,
+    Request,
+}
+
+impl From<&reqwest::Error> for FailureKind {
+    fn from(err: &reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout
+        } else if err.is_connect() {
+            Self::Connect
+        } else if err.is_status() {
+            Self::Status
+        } else if err.is_redirect() {
+            Self::Redirect
+        } else {
+            Self::Request
+        }
+    }
+}
+
+/// When to retry failed expansions.
+#[derive(Debug, Clone, Copy)]
+pub struct ExpansionRetryPolicy {
+    /// A failure expires after this duration, and then the URL is expanded again
+    pub retry_after: Duration,
+    /// Stop retrying after this number of failed attempts
+    pub max_attempts: u32,
+}
+
+impl Default for ExpansionRetryPolicy {
+    fn default() -> Self {
+        Self {
+            retry_after: Duration::from_secs(24 * 60 * 60),
+            max_attempts: 5,
+        }
+    }
+}
+
+impl Expansion {
+    /// The final destination, if resolved.
+    pub fn destination(&self) -> Option<&str> {
+        match self {
+            Self::Resolved(chain) => chain.destination(),
+            Self::Failed { .. } => None,
+        }
+    }
+
+    /// Check if it should be expanded again at `now` (seconds since the Unix epoch).
+    fn should_retry(&self, policy: &ExpansionRetryPolicy, now: u64) -> bool {
+        match self {
+            Self::Resolved(_) => false,
+            Self::Failed { failure } => {
+                failure.attempts < policy.max_attempts
+                    && now.saturating_sub(failure.failed_at) >= policy.retry_after.as_secs()
+            }
+        }
+    }
+}
+
+/// Seconds since the Unix epoch.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Where a shortcut URL redirects to, hop by hop.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedirectChain {
+    /// Redirects in order, not including the shortcut URL itself
+    pub hops: Vec<Hop>,
+    /// Status code of the final destination, or `None` if unknown
+    pub status: Option<u16>,
+}
+
+/// A redirect to `url`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hop {
+    /// Status code of the redirect response (e.g. 301), or `None` if unknown
+    pub status: Option<u16>,
+    /// The `Location`
+    pub url: String,
+}
+
+impl RedirectChain {
+    /// The URL that the shortcut URL redirects to directly.
+    pub fn first_hop(&self) -> Option<&str> {
+        self.hops.first().map(|hop| hop.url.as_str())
+    }
+
+    /// The final destination.
+    pub fn destination(&self) -> Option<&str> {
+        self.hops.last().map(|hop| hop.url.as_str())
+    }
+}
 
 impl ShortcutUrlCache {
     pub fn new() -> Self {
-        Self(HashMap::new())
-    }
-
-    /// Expand a shortcut URL (e.g. https://t.cn/zHVwH1H)
-    fn expand(&mut self, url: &str, client: &Client) -> Result<String, reqwest::Error> {
-        if url.starts_with("https://t.cn/") || url.starts_with("http://t.cn/") {
-            println!("🔎 Expand “{}”.", url);
-
-            match self.0.entry(url.to_owned()) {
-                Entry::Occupied(e) => Ok(e.get().to_owned()),
-                Entry::Vacant(e) => {
-                    let response = client.get(e.key()).send()?;
-
-                    Ok(
-                        e.insert(if let Some(location) = response.headers().get("location") {
-                            location.to_str().unwrap().to_owned()
-                        } else {
-                            response.url().as_str().to_owned()
-                        })
-                        .to_owned(),
-                    )
+        Self(BTreeMap::new())
+    }
+
+    /// Check if `url` is a shortcut URL (e.g. https://t.cn/zHVwH1H) of any of the `shorteners`
+    fn is_shortcut(url: &str, shorteners: &HashSet<String>) -> bool {
+        Url::parse(url).is_ok_and(|url| {
+            matches!(url.scheme(), "http" | "https")
+                && url.path() != "/" // The home page of the shortener
+                && url
+                    .host_str()
+                    .is_some_and(|host| shorteners.contains(host))
+        })
+    }
+
+    /// Expand uncached shortcut URLs concurrently, and merge them into the cache.
+    ///
+    /// Failures are cached as well, and expanded again according to `retry`.
+    fn expand_all(
+        &mut self,
+        urls: BTreeSet<String>,
+        client: &HttpClient,
+        pool: &WorkerPool,
+        retry: &ExpansionRetryPolicy,
+    ) {
+        let now = unix_now();
+        let urls: Vec<_> = urls
+            .into_iter()
+            .filter(|url| self.0.get(url).is_none_or(|e| e.should_retry(retry, now)))
+            .collect();
+        if urls.is_empty() {
+            return;
+        }
+
+        println!(
+            "🔎 Expanding {} short URLs with {} workers…",
+            urls.len(),
+            pool.workers
+        );
+        let results = pool.map(&urls, |url| Self::resolve(url, client));
+
+        // Merge in the order of `urls`, to be deterministic
+        let mut n_failures = 0;
+        for (url, result) in urls.into_iter().zip(results) {
+            let expansion = match result {
+                Ok(chain) => Expansion::Resolved(chain),
+                Err(err) => {
+                    eprintln!("failed to expand “{url}”: {err}.");
+                    n_failures += 1;
+
+                    let attempts = match self.0.get(&url) {
+                        Some(Expansion::Failed { failure }) => failure.attempts + 1,
+                        _ => 1,
+                    };
+                    Expansion::Failed {
+                        failure: ExpansionFailure {
+                            kind: (&err).into(),
+                            message: err.without_url().to_string(),
+                            failed_at: unix_now(),
+                            attempts,
+                        },
+                    }
+                }
+            };
+            self.0.insert(url, expansion);
+        }
+
+        if n_failures > 0 {
+            eprintln!("⚠️ Failed to expand {n_failures} short URLs. They are kept as is.");
+        }
+    }
+
+    /// Request a shortcut URL and follow its redirects.
+    fn resolve(url: &str, client: &HttpClient) -> Result<RedirectChain, reqwest::Error> {
+        println!("🔎 Expand “{}”.", url);
+
+        let mut hops = Vec::new();
+        let mut current = url.to_owned();
+        while hops.len() < MAX_REDIRECTS {
+            let response = match client.get_without_redirect(&current) {
+                Ok(response) => response,
+                // The shortener itself fails
+                Err(err) if hops.is_empty() => return Err(err),
+                // The destination is dead, but the shortener has told where it points
+                Err(err) => {
+                    eprintln!("failed to request “{current}” redirected from “{url}”: {err}.");
+                    return Ok(RedirectChain { hops, status: None });
+                }
+            };
+            let status = response.status();
+
+            // `Location` might be relative
+            let next = response
+                .headers()
+                .get("location")
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| response.url().join(location).ok())
+                .filter(|_| status.is_redirection());
+            match next {
+                Some(next) => {
+                    current = next.to_string();
+                    hops.push(Hop {
+                        status: Some(status.as_u16()),
+                        url: current.to_owned(),
+                    });
+                }
+                None if hops.is_empty() => {
+                    // The shortener itself fails
+                    response.error_for_status()?;
+                    return Ok(RedirectChain {
+                        hops,
+                        status: Some(status.as_u16()),
+                    });
+                }
+                None => {
+                    return Ok(RedirectChain {
+                        hops,
+                        status: Some(status.as_u16()),
+                    });
                 }
             }
-        } else {
-            Ok(url.to_owned())
         }
+
+        eprintln!("too many redirects from “{url}”, stop at “{current}”.");
+        Ok(RedirectChain { hops, status: None })
+    }
+
+    /// Replace `link`’s URL with its cached destination, if any.
+    fn apply(&self, link: &mut Link) {
+        if let Some(destination) = self.0.get(&link.url).and_then(|e| e.destination()) {
+            link.set_url(destination.to_owned());
+        }
+    }
+
+    /// Iterate over shortcut URLs and their expansions, sorted by shortcut URLs.
+    fn iter(&self) -> impl Iterator<Item = (&str, &Expansion)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v))
     }
 }
 
@@ -194,79 +914,299 @@ impl Default for Driver {
         Self {
             episodes: HashMap::new(),
             short_urls: ShortcutUrlCache::new(),
-            client: Client::new(),
+            diagnostics: Diagnostics::default(),
+            shorteners: DEFAULT_SHORTENERS.iter().map(|&h| h.to_owned()).collect(),
+            expansion_retry: ExpansionRetryPolicy::default(),
+            client: HttpClient::default(),
+            show_notes: None,
+            #[cfg(feature = "sqlite")]
+            database: None,
         }
     }
 }
 
 impl Driver {
+    /// Write episodes to `database` as well, starting with syncing it to the cache.
+    #[cfg(feature = "sqlite")]
+    pub fn with_database(mut self, mut database: Database) -> rusqlite::Result<Self> {
+        // The cache may have changed while no database was attached
+        database.replace_episodes(self.episodes.iter())?;
+        database.save_short_urls(self.short_urls.iter())?;
+
+        self.database = Some(database);
+        Ok(self)
+    }
+
+    /// Keep the raw HTML of show notes in `store`.
+    pub fn with_show_notes(self, store: ShowNotesStore) -> Self {
+        Self {
+            show_notes: Some(store),
+            ..self
+        }
+    }
+
+    /// Use `client` for HTTP requests.
+    pub fn with_client(self, client: HttpClient) -> Self {
+        Self { client, ..self }
+    }
+
+    /// Expand URLs of these hosts.
+    pub fn with_shorteners(self, hosts: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            shorteners: hosts.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Retry failed expansions of shortcut URLs according to `policy`.
+    pub fn with_expansion_retry(self, policy: ExpansionRetryPolicy) -> Self {
+        Self {
+            expansion_retry: policy,
+            ..self
+        }
+    }
+
     /// Save links in an episode’s show notes
+    ///
+    /// Known episodes are processed again only if their show notes changed.
     pub fn push_episode(
         &mut self,
-        episode: Episode,
+        mut episode: Episode,
         show_notes: &str,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        if let Entry::Vacant(ep) = self.episodes.entry(episode) {
-            let links =
-                Self::push_episode_raw(ep.key(), show_notes, &mut self.short_urls, &self.client)
-                    .inspect_err(|err| {
-                        eprintln!("failed to push episode “{}”: {err}.", ep.key().name)
-                    })?;
-            ep.insert(links);
+        // Keep show notes of known episodes as well, to fill in older caches
+        if let Some(store) = &self.show_notes {
+            store.save(&episode, show_notes)?;
+        }
+        episode.content_hash = Some(content_hash(show_notes));
+
+        if self
+            .episodes
+            .get_key_value(&episode)
+            .is_some_and(|(known, _)| known.content_hash == episode.content_hash)
+        {
+            // Refresh metadata only
+            let (known, links) = self.episodes.remove_entry(&episode).unwrap();
+            episode.audio_url = episode.audio_url.or(known.audio_url);
+
+            #[cfg(feature = "sqlite")]
+            if let Some(database) = &mut self.database
+                && known.modified != episode.modified
+            {
+                database.save_episode(&episode, &links)?;
+            }
+
+            self.episodes.insert(episode, links);
+            return Ok(());
+        }
+
+        let mut links = Self::push_episode_raw(&episode, show_notes, &mut self.diagnostics)
+            .inspect_err(|err| eprintln!("failed to push episode “{}”: {err}.", episode.name))?;
+        for link in &mut links {
+            self.short_urls.apply(link);
+        }
+
+        // Fall back to the audio download link in the show notes
+        if episode.audio_url.is_none() {
+            episode.audio_url = links
+                .iter()
+                .find(|l| l.role != LinkRole::Reference && l.url.ends_with(".mp3"))
+                .map(|l| l.url.to_owned());
+        }
+
+        if let Some((known, old_links)) = self.episodes.remove_entry(&episode) {
+            // Caches without hashes cannot tell, so only changes of links are logged
+            if known.content_hash.is_some() {
+                println!(
+                    "✏️ Show notes of “{}” changed (modified: {} → {}).",
+                    episode.name,
+                    known.modified.as_deref().unwrap_or("unknown"),
+                    episode.modified.as_deref().unwrap_or("unknown"),
+                );
+            }
+            let changes = LinkChanges::between(&old_links, &links);
+            if !changes.is_empty() {
+                println!(
+                    "✏️ Links of “{}” changed: +{} −{}",
+                    episode.name,
+                    changes.added.len(),
+                    changes.removed.len()
+                );
+                for url in &changes.added {
+                    println!("  + {url}");
+                }
+                for url in &changes.removed {
+                    println!("  - {url}");
+                }
+            }
         }
 
+        #[cfg(feature = "sqlite")]
+        if let Some(database) = &mut self.database {
+            database.save_episode(&episode, &links)?;
+        }
+
+        self.episodes.insert(episode, links);
+
         Ok(())
     }
 
+    /// Expand shortcut URLs in all episodes in a batch
+    ///
+    /// Links are stored unexpanded by [`push_episode`], and replaced here.
+    /// Links that fail to expand are kept as is, and might be expanded in a later call.
+    pub fn expand_short_urls(
+        &mut self,
+        pool: &WorkerPool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let pending = self
+            .episodes
+            .values()
+            .flatten()
+            .filter(|link| ShortcutUrlCache::is_shortcut(&link.url, &self.shorteners))
+            .map(|link| link.url.to_owned())
+            .collect();
+        self.short_urls
+            .expand_all(pending, &self.client, pool, &self.expansion_retry);
+
+        // Replace with final destinations
+        for link in self.episodes.values_mut().flatten() {
+            self.short_urls.apply(link);
+        }
+
+        #[cfg(feature = "sqlite")]
+        if let Some(database) = &mut self.database {
+            database.save_short_urls(self.short_urls.iter())?;
+        }
+
+        Ok(())
+    }
+
+    /// Extract links again from stored show notes, without the Internet.
+    ///
+    /// Shortcut URLs are expanded by the cache only.
+    pub fn reextract(
+        &mut self,
+        store: &ShowNotesStore,
+    ) -> Result<Reextraction, Box<dyn std::error::Error>> {
+        let mut result = Reextraction::default();
+
+        let mut episodes: Vec<_> = self.episodes.keys().cloned().collect();
+        episodes.sort_by_cached_key(Episode::id);
+        for episode in episodes {
+            let Some(show_notes) = store.load(&episode)? else {
+                result.missing.push(episode);
+                continue;
+            };
+
+            let mut links = Self::push_episode_raw(&episode, &show_notes, &mut self.diagnostics)?;
+            for link in &mut links {
+                self.short_urls.apply(link);
+            }
+
+            let old_links = self
+                .episodes
+                .get_mut(&episode)
+                .expect("the episode should be known");
+            let changes = LinkChanges::between(old_links, &links);
+            if *old_links != links {
+                *old_links = links;
+
+                #[cfg(feature = "sqlite")]
+                if let Some(database) = &mut self.database {
+                    database.save_episode(&episode, old_links)?;
+                }
+
+                result.changed.push((episode, changes));
+            }
+        }
+
+        Ok(result)
+    }
+
     /// [`push_episode`] without the episode cache
     fn push_episode_raw(
         episode: &Episode,
         show_notes: &str,
-        short_urls: &mut ShortcutUrlCache,
-        client: &Client,
-    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Vec<Link>, Box<dyn std::error::Error>> {
         println!("🚀 Fetching “{}”…", episode.name);
+        diagnostics.mark_checked(&episode.url);
 
         let document = Html::parse_document(show_notes);
 
-        let selector = Selector::parse("a")?;
-        let links = document
-            .select(&selector)
-            .filter_map(|a| {
-                if let Some(url) = a.value().attr("href") {
-                    Some(url)
-                } else {
-                    // Example: Footer of https://www.thetype.com/typechat/ep-001/
-                    let html = &a.html();
-                    if html == "<a>｜</a>" || html == "<a></a>" {
-                        None
-                    } else {
-                        panic!("fail to get href from an anchor: {html}")
-                    }
+        // Headings are selected as well, in document order
+        let selector = Selector::parse("a, h1, h2, h3, h4, h5, h6")?;
+        let subscription = Selector::parse(SUBSCRIPTION_SELECTOR)?;
+        let media = Selector::parse(MEDIA_SELECTOR)?;
+
+        let mut section = None;
+        let mut links = Vec::new();
+        for element in document.select(&selector) {
+            if element.value().name() != "a" {
+                section = Some(collapsed_text(element));
+                continue;
+            }
+
+            let a = element;
+            let kind = match a.value().attr("href") {
+                Some(href) => DiagnosticKind::check_href(href),
+                // Separators. Example: Footer of https://www.thetype.com/typechat/ep-001/
+                None if matches!(collapsed_text(a).as_str(), "｜" | "") => continue,
+                None => Some(DiagnosticKind::MissingHref),
+            };
+            if let Some(kind) = kind {
+                diagnostics.push(Diagnostic {
+                    episode: episode.url.to_owned(),
+                    kind,
+                    html: a.html(),
+                });
+                if kind.skips_link() {
+                    continue;
                 }
-            })
-            .map(|url| Ok(short_urls.expand(url, client)?.to_owned()))
-            .collect::<Result<Vec<_>, reqwest::Error>>()?;
+            }
+            let href = a
+                .value()
+                .attr("href")
+                .expect("href should exist unless skipped");
 
-        println!("✅ Got {} links.", links.len());
+            let block = a
+                .ancestors()
+                .filter_map(ElementRef::wrap)
+                .find(|e| BLOCK_ELEMENTS.contains(&e.value().name()));
+            links.push(Link {
+                text: collapsed_text(a),
+                index: links.len(),
+                context: block.map(collapsed_text),
+                section: section.clone(),
+                role: LinkRole::classify(a, &subscription, &media),
+                ..Link::new(href.to_owned())
+            });
+        }
 
-        println!("💤 (Sleep for a second)");
-        thread::sleep(time::Duration::from_secs(1));
+        println!("✅ Got {} links.", links.len());
 
         Ok(links)
     }
 
+    /// Iterate over shortcut URLs and their expansions, sorted by shortcut URLs.
+    pub fn short_urls(&self) -> impl Iterator<Item = (&str, &Expansion)> {
+        self.short_urls.iter()
+    }
+
     /// Build a `Driver` from JSON caches.
     pub fn from_cache(episodes: Option<String>, short_urls: Option<String>) -> io::Result<Self> {
-        let episodes: HashMap<Episode, Vec<String>> = if let Some(episodes) = episodes {
-            let vec: Vec<(_, _)> = serde_json::from_str(&episodes)?;
-            vec.into_iter().collect()
+        let episodes: HashMap<Episode, Vec<Link>> = if let Some(episodes) = episodes {
+            let vec: Vec<CachedEpisode<Episode, Vec<Link>>> = cache::EPISODES.load(&episodes)?;
+            vec.into_iter()
+                .map(|CachedEpisode { episode, links }| (episode, links))
+                .collect()
         } else {
             HashMap::new()
         };
 
         let short_urls: ShortcutUrlCache = if let Some(short_urls) = short_urls {
-            serde_json::from_str(&short_urls)?
+            cache::SHORT_URLS.load(&short_urls)?
         } else {
             ShortcutUrlCache::new()
         };
@@ -279,10 +1219,17 @@ impl Driver {
     }
 
     /// Dump the `Driver` as JSON caches.
-    pub fn to_cache(&self) -> io::Result<(String, String)> {
-        let episodes: Vec<(_, _)> = self.episodes.iter().collect();
-        let episodes_json = serde_json::to_string(&episodes)?;
-        let short_urls_json = serde_json::to_string(&self.short_urls)?;
+    ///
+    /// Episodes are sorted by [`EpisodeId`], so that the caches change only if the data change.
+    pub fn to_cache(&self, pretty: bool) -> io::Result<(String, String)> {
+        let mut episodes: Vec<_> = self
+            .episodes
+            .iter()
+            .map(|(episode, links)| CachedEpisode { episode, links })
+            .collect();
+        episodes.sort_by_cached_key(|e| (e.episode.id(), e.episode.url.to_owned()));
+        let episodes_json = cache::EPISODES.dump(&episodes, pretty)?;
+        let short_urls_json = cache::SHORT_URLS.dump(&self.short_urls, pretty)?;
         Ok((episodes_json, short_urls_json))
     }
 }
@@ -0,0 +1,125 @@
+//! Collect anomalies in show notes, without stopping the run.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, io,
+};
+
+use url::{ParseError, Url};
+
+/// An anomaly found in an episode’s show notes.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// URL of the episode
+    pub episode: String,
+    pub kind: DiagnosticKind,
+    /// HTML of the anchor
+    pub html: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// `<a>` without `href` (skipped)
+    MissingHref,
+    /// `href=""` (skipped)
+    EmptyHref,
+    /// `href="javascript:…"` (skipped)
+    JavascriptHref,
+    /// An `href` that is neither an absolute URL nor a sane relative URL (kept)
+    MalformedUrl,
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::MissingHref => "missing href",
+            Self::EmptyHref => "empty href",
+            Self::JavascriptHref => "javascript: link",
+            Self::MalformedUrl => "malformed URL",
+        })
+    }
+}
+
+impl DiagnosticKind {
+    /// Check an `href`, and return the anomaly if any.
+    pub fn check_href(href: &str) -> Option<Self> {
+        let href = href.trim();
+        if href.is_empty() {
+            Some(Self::EmptyHref)
+        } else if href
+            .get(.."javascript:".len())
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("javascript:"))
+        {
+            Some(Self::JavascriptHref)
+        } else {
+            match Url::parse(href) {
+                Ok(_) => None,
+                // Relative URLs should at least start from the root, the query, or the fragment.
+                Err(ParseError::RelativeUrlWithoutBase) if href.starts_with(['/', '?', '#']) => {
+                    None
+                }
+                // Example: `写真歴史博物館` in https://www.thetype.com/typechat/ep-039/
+                Err(_) => Some(Self::MalformedUrl),
+            }
+        }
+    }
+
+    /// Check if the link should be skipped.
+    pub fn skips_link(&self) -> bool {
+        !matches!(self, Self::MalformedUrl)
+    }
+}
+
+/// A collector of [`Diagnostic`]s.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+    /// URLs of episodes checked so far
+    checked: HashSet<String>,
+}
+
+impl Diagnostics {
+    /// Record that an episode has been checked, whether or not anomalies are found.
+    pub fn mark_checked(&mut self, episode: &str) {
+        self.checked.insert(episode.to_owned());
+    }
+
+    /// Check if all `episodes` (URLs) have been checked, so that the report is complete.
+    pub fn covers<'a>(&self, mut episodes: impl Iterator<Item = &'a str>) -> bool {
+        episodes.all(|url| self.checked.contains(url))
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        eprintln!(
+            "⚠️ {} in “{}”: {}",
+            diagnostic.kind, diagnostic.episode, diagnostic.html
+        );
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /// Write a markdown report, grouped by episodes.
+    pub fn write_report(&self, mut buffer: impl io::Write) -> io::Result<()> {
+        let mut by_episode: BTreeMap<&str, Vec<&Diagnostic>> = BTreeMap::new();
+        for d in &self.diagnostics {
+            by_episode.entry(&d.episode).or_default().push(d);
+        }
+
+        buffer.write_all(b"# Diagnostics\n")?;
+        for (episode, diagnostics) in by_episode {
+            writeln!(buffer, "\n## <{episode}>\n")?;
+            for d in diagnostics {
+                writeln!(buffer, "- {}: `{}`", d.kind, d.html)?;
+            }
+        }
+
+        Ok(())
+    }
+}
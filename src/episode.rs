@@ -0,0 +1,411 @@
+//! Identify episodes, regardless of how they are spelled, and parse their titles.
+
+use std::{cmp::Ordering, convert::Infallible, fmt, str::FromStr};
+
+use reqwest::Url;
+
+/// The show’s name at the start of each title.
+pub const SHOW_PREFIX: &str = "字谈字畅";
+
+/// Identifier of an episode.
+///
+/// Numbered and bonus episodes are sorted by numbers, and special episodes come last.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EpisodeId {
+    /// Example: `ep-250` or `字谈字畅 250：…`
+    Numbered(u32),
+    /// An extra episode following a numbered one
+    ///
+    /// Example: `ep-120-bonus` or `字谈字畅 120 番外：…`
+    Bonus { number: u32, tag: String },
+    /// An episode without a number, identified by its slug
+    ///
+    /// Example: `ep-special`
+    Special(String),
+}
+
+impl EpisodeId {
+    /// Parse a TypeChat URL, e.g. `https://www.thetype.com/typechat/ep-250/`.
+    ///
+    /// Return `None` if it is not an episode’s URL.
+    pub fn from_url(url: &str) -> Option<Self> {
+        let url = Url::parse(url).ok()?;
+        if !matches!(url.host_str()?, "www.thetype.com" | "thetype.com") {
+            return None;
+        }
+        match url
+            .path_segments()?
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()[..]
+        {
+            ["typechat", slug] => Self::from_slug(slug),
+            _ => None,
+        }
+    }
+
+    /// Parse a title, e.g. `字谈字畅 250：增补修订传心意`.
+    ///
+    /// Return `None` if there is no number.
+    pub fn from_title(title: &str) -> Option<Self> {
+        let title = Title::parse(title);
+        Some(Self::new(title.number?, title.tag))
+    }
+
+    /// Parse a slug, e.g. `ep-250`.
+    fn from_slug(slug: &str) -> Option<Self> {
+        let rest = slug.strip_prefix("ep-")?;
+        Some(match split_number(rest) {
+            Some((number, tag)) => Self::new(number, tag.trim_start_matches(['-', '_'])),
+            None => Self::Special(rest.to_owned()),
+        })
+    }
+
+    fn new(number: u32, tag: &str) -> Self {
+        if tag.is_empty() {
+            Self::Numbered(number)
+        } else {
+            Self::Bonus {
+                number,
+                tag: tag.to_owned(),
+            }
+        }
+    }
+
+    /// The number of the episode, or of the episode that a bonus one follows.
+    pub fn number(&self) -> Option<u32> {
+        match self {
+            Self::Numbered(number) | Self::Bonus { number, .. } => Some(*number),
+            Self::Special(_) => None,
+        }
+    }
+
+    fn sort_key(&self) -> (Option<u32>, &str) {
+        match self {
+            Self::Numbered(_) => (self.number(), ""),
+            Self::Bonus { tag, .. } => (self.number(), tag),
+            Self::Special(slug) => (None, slug),
+        }
+    }
+}
+
+/// Split leading ASCII digits and the rest.
+fn split_number(s: &str) -> Option<(u32, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    Some((s[..end].parse().ok()?, &s[end..]))
+}
+
+impl Ord for EpisodeId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = (self.sort_key(), other.sort_key());
+        // `None` (special) comes last
+        (a.0.is_none(), a).cmp(&(b.0.is_none(), b))
+    }
+}
+
+impl PartialOrd for EpisodeId {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Format as a slug, e.g. `ep-001`, `ep-120-bonus`, or `ep-special`.
+impl fmt::Display for EpisodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Numbered(number) => write!(f, "ep-{number:03}"),
+            Self::Bonus { number, tag } => write!(f, "ep-{number:03}-{tag}"),
+            Self::Special(slug) => write!(f, "ep-{slug}"),
+        }
+    }
+}
+
+/// Parse a slug (`ep-250`) or a bare number (`250`).
+///
+/// Anything else is taken as a special episode’s slug.
+impl FromStr for EpisodeId {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Ok(match split_number(s) {
+            Some((number, "")) => Self::Numbered(number),
+            _ => Self::from_slug(s).unwrap_or_else(|| Self::Special(s.to_owned())),
+        })
+    }
+}
+
+/// Parts of an episode’s title.
+///
+/// Example: `字谈字畅 170：某某访谈（上）`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Title<'a> {
+    /// The show’s name, e.g. `字谈字畅`
+    pub show: Option<&'a str>,
+    /// e.g. `170`
+    pub number: Option<u32>,
+    /// Words between the number and the colon, e.g. `番外` (bonus episodes only)
+    pub tag: &'a str,
+    /// e.g. `某某访谈`
+    pub main: &'a str,
+    /// e.g. `上`
+    pub part: Option<Part>,
+}
+
+/// A marker of a multi-part or special episode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Part {
+    /// `上`
+    First,
+    /// `下`
+    Second,
+    /// `特别节目`
+    Special,
+}
+
+impl Part {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "上" => Some(Self::First),
+            "下" => Some(Self::Second),
+            "特别节目" => Some(Self::Special),
+            _ => None,
+        }
+    }
+
+    /// Check if `s` consists of the marker only.
+    fn is_in(self, s: &str) -> bool {
+        Self::parse(unparenthesize(s)) == Some(self)
+    }
+}
+
+impl fmt::Display for Part {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::First => "上",
+            Self::Second => "下",
+            Self::Special => "特别节目",
+        })
+    }
+}
+
+impl<'a> Title<'a> {
+    /// Parse a title. Anything unrecognized is kept in [`Title::main`].
+    pub fn parse(title: &'a str) -> Self {
+        let title = title.trim();
+        let Some(rest) = title.strip_prefix(SHOW_PREFIX) else {
+            let (main, part) = split_part(title);
+            return Self {
+                show: None,
+                number: None,
+                tag: "",
+                main,
+                part,
+            };
+        };
+
+        let (head, main) = match rest.split_once(['：', ':']) {
+            Some((head, main)) => (head.trim(), main.trim()),
+            None => ("", rest.trim()),
+        };
+        let (number, tag) = match split_number(head) {
+            Some((number, tag)) => (Some(number), tag.trim()),
+            None => (None, head),
+        };
+        let (main, part) = split_part(main);
+
+        // The marker may be in the head instead, e.g. `字谈字畅 特别节目：…` or `字谈字畅 170 下：…`
+        let (tag, part) = match part.or_else(|| Part::parse(unparenthesize(tag))) {
+            Some(part) if part.is_in(tag) => ("", Some(part)),
+            part => (tag, part),
+        };
+
+        Self {
+            show: Some(SHOW_PREFIX),
+            number,
+            tag,
+            main,
+            part,
+        }
+    }
+
+    /// Check if it is one part of a multi-part episode.
+    pub fn is_multi_part(&self) -> bool {
+        matches!(self.part, Some(Part::First | Part::Second))
+    }
+}
+
+/// Split a trailing part marker, e.g. `某某访谈（上）` → `某某访谈`, `上`.
+fn split_part(s: &str) -> (&str, Option<Part>) {
+    for (open, close) in [('（', '）'), ('(', ')')] {
+        if let Some(rest) = s.strip_suffix(close)
+            && let Some((main, marker)) = rest.rsplit_once(open)
+            && let Some(part) = Part::parse(marker.trim())
+        {
+            return (main.trim_end(), Some(part));
+        }
+    }
+    (s, None)
+}
+
+/// Remove surrounding parentheses, e.g. `（上）` → `上`.
+fn unparenthesize(s: &str) -> &str {
+    s.trim_start_matches(['（', '('])
+        .trim_end_matches(['）', ')'])
+        .trim()
+}
+
+/// Format a concise label without the show’s name, e.g. `170 某某访谈（上）`.
+impl fmt::Display for Title<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.number, self.tag) {
+            (Some(number), "") => write!(f, "{number} ")?,
+            (Some(number), tag) => write!(f, "{number} {tag} ")?,
+            (None, "") => {}
+            (None, tag) => write!(f, "{tag} ")?,
+        }
+        f.write_str(self.main)?;
+        match self.part {
+            Some(part) => write!(f, "（{part}）"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_from_url() {
+        let from_url =
+            |slug| EpisodeId::from_url(&format!("https://www.thetype.com/typechat/{slug}/"));
+        assert_eq!(from_url("ep-250"), Some(EpisodeId::Numbered(250)));
+        assert_eq!(
+            from_url("ep-120-bonus"),
+            Some(EpisodeId::Bonus {
+                number: 120,
+                tag: "bonus".to_owned()
+            })
+        );
+        assert_eq!(
+            from_url("ep-special"),
+            Some(EpisodeId::Special("special".to_owned()))
+        );
+        assert_eq!(from_url("about"), None);
+        assert_eq!(
+            EpisodeId::from_url("https://example.com/typechat/ep-250/"),
+            None
+        );
+    }
+
+    #[test]
+    fn id_from_str() {
+        for s in ["250", "ep-250", " ep-250 "] {
+            assert_eq!(s.parse(), Ok(EpisodeId::Numbered(250)), "{s}");
+        }
+        assert_eq!(
+            "ep-120-bonus".parse(),
+            Ok(EpisodeId::Bonus {
+                number: 120,
+                tag: "bonus".to_owned()
+            })
+        );
+        assert_eq!(
+            "special".parse(),
+            Ok(EpisodeId::Special("special".to_owned()))
+        );
+        assert_eq!(EpisodeId::Numbered(1).to_string(), "ep-001");
+    }
+
+    #[test]
+    fn id_order() {
+        let ids: Vec<EpisodeId> = ["ep-9", "ep-9-bonus", "ep-10", "ep-special"]
+            .into_iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let mut sorted = ids.clone();
+        sorted.reverse();
+        sorted.sort();
+        assert_eq!(sorted, ids);
+    }
+
+    #[test]
+    fn title() {
+        let title = Title::parse("字谈字畅 250：增补修订传心意");
+        assert_eq!(
+            title,
+            Title {
+                show: Some(SHOW_PREFIX),
+                number: Some(250),
+                tag: "",
+                main: "增补修订传心意",
+                part: None,
+            }
+        );
+        assert_eq!(title.to_string(), "250 增补修订传心意");
+
+        // Not a TypeChat title
+        let title = Title::parse("某某访谈");
+        assert_eq!(
+            (title.show, title.number, title.main),
+            (None, None, "某某访谈")
+        );
+    }
+
+    #[test]
+    fn title_tag() {
+        let title = Title::parse("字谈字畅 120 番外：某某访谈");
+        assert_eq!((title.number, title.tag), (Some(120), "番外"));
+        assert_eq!(title.to_string(), "120 番外 某某访谈");
+
+        // Tags are kept without a number
+        let title = Title::parse("字谈字畅 番外：标题");
+        assert_eq!((title.number, title.tag), (None, "番外"));
+        assert_eq!(title.to_string(), "番外 标题");
+        // Too large to be a number
+        let title = Title::parse("字谈字畅 99999999999：x");
+        assert_eq!((title.number, title.tag), (None, "99999999999"));
+        assert_eq!(title.to_string(), "99999999999 x");
+    }
+
+    #[test]
+    fn title_part_in_main() {
+        for s in [
+            "字谈字畅 170：某某访谈（上）",
+            "字谈字畅 170: 某某访谈 (上)",
+        ] {
+            let title = Title::parse(s);
+            assert_eq!(
+                (title.number, title.tag, title.main, title.part),
+                (Some(170), "", "某某访谈", Some(Part::First)),
+                "{s}"
+            );
+            assert!(title.is_multi_part());
+            assert_eq!(title.to_string(), "170 某某访谈（上）");
+        }
+
+        let title = Title::parse("某某访谈（下）");
+        assert_eq!((title.main, title.part), ("某某访谈", Some(Part::Second)));
+    }
+
+    #[test]
+    fn title_part_in_head() {
+        for s in ["字谈字畅 170 下：某某访谈", "字谈字畅 170（下）：某某访谈"]
+        {
+            let title = Title::parse(s);
+            assert_eq!(
+                (title.number, title.tag, title.main, title.part),
+                (Some(170), "", "某某访谈", Some(Part::Second)),
+                "{s}"
+            );
+        }
+
+        let title = Title::parse("字谈字畅 特别节目：新年快乐");
+        assert_eq!(
+            (title.number, title.tag, title.main, title.part),
+            (None, "", "新年快乐", Some(Part::Special))
+        );
+        assert!(!title.is_multi_part());
+        assert_eq!(title.to_string(), "新年快乐（特别节目）");
+    }
+}
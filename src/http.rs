@@ -0,0 +1,192 @@
+//! HTTP client with rate limiting.
+//!
+//! Only actual requests are throttled, so cached data can be processed at full speed.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicUsize, Ordering::SeqCst},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use reqwest::{
+    Url,
+    blocking::{Client, ClientBuilder, Response},
+    redirect,
+};
+
+/// A token-bucket rate limiter, with a separate bucket for each host.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Tokens added per second
+    rate: f64,
+    /// Capacity of a bucket
+    burst: f64,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    /// Allow `rate` requests per second to each host, and at most `burst` requests at once.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            rate,
+            burst: burst.max(1).into(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Block until a request to `host` is allowed.
+    pub fn acquire(&self, host: &str) {
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let now = Instant::now();
+                let bucket = buckets.entry(host.to_owned()).or_insert(TokenBucket {
+                    tokens: self.burst,
+                    updated_at: now,
+                });
+
+                // Refill
+                let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+                bucket.updated_at = now;
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)
+            };
+
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// A [`Client`] that waits for its [`RateLimiter`] before each request.
+///
+/// Clones share the same limiter.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    /// A client that does not follow redirects
+    manual_redirect_client: Client,
+    limiter: Arc<RateLimiter>,
+}
+
+impl HttpClient {
+    /// Build clients from `builder`, which is called twice.
+    pub fn build(
+        builder: impl Fn() -> ClientBuilder,
+        limiter: RateLimiter,
+    ) -> reqwest::Result<Self> {
+        Ok(Self {
+            client: builder().build()?,
+            manual_redirect_client: builder().redirect(redirect::Policy::none()).build()?,
+            limiter: Arc::new(limiter),
+        })
+    }
+
+    /// Send a GET request to `url`.
+    pub fn get(&self, url: &str) -> reqwest::Result<Response> {
+        self.execute(&self.client, url)
+    }
+
+    /// Send a GET request to `url`, without following redirects.
+    pub fn get_without_redirect(&self, url: &str) -> reqwest::Result<Response> {
+        self.execute(&self.manual_redirect_client, url)
+    }
+
+    fn execute(&self, client: &Client, url: &str) -> reqwest::Result<Response> {
+        let request = client.get(url).build()?;
+        self.limiter
+            .acquire(request.url().host_str().unwrap_or_default());
+        client.execute(request)
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::build(Client::builder, RateLimiter::new(1.0, 1))
+            .expect("failed to build HTTP clients")
+    }
+}
+
+/// A bounded pool of worker threads for URLs.
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerPool {
+    /// Maximum number of workers
+    pub workers: usize,
+    /// Maximum number of workers working on the same host
+    pub per_host: usize,
+}
+
+impl WorkerPool {
+    /// Apply `f` to each URL concurrently.
+    ///
+    /// Results are in the same order as `urls`, regardless of which finishes first.
+    pub fn map<T: Send>(&self, urls: &[String], f: impl Fn(&str) -> T + Sync) -> Vec<T> {
+        let per_host = self.per_host.max(1);
+
+        let next = AtomicUsize::new(0);
+        let busy_hosts: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+        let released = Condvar::new();
+        let results: Vec<Mutex<Option<T>>> = urls.iter().map(|_| Mutex::new(None)).collect();
+
+        thread::scope(|scope| {
+            for _ in 0..self.workers.max(1).min(urls.len()) {
+                scope.spawn(|| {
+                    loop {
+                        let i = next.fetch_add(1, SeqCst);
+                        let Some(url) = urls.get(i) else {
+                            break;
+                        };
+                        let host = host_of(url);
+
+                        // Wait for a free slot of the host
+                        {
+                            let mut busy = busy_hosts.lock().unwrap();
+                            while busy.get(&host).is_some_and(|n| *n >= per_host) {
+                                busy = released.wait(busy).unwrap();
+                            }
+                            *busy.entry(host.to_owned()).or_default() += 1;
+                        }
+
+                        let result = f(url);
+
+                        *busy_hosts.lock().unwrap().get_mut(&host).unwrap() -= 1;
+                        released.notify_all();
+
+                        *results[i].lock().unwrap() = Some(result);
+                    }
+                });
+            }
+        });
+
+        results
+            .into_iter()
+            .map(|r| {
+                r.into_inner()
+                    .unwrap()
+                    .expect("every URL should be processed")
+            })
+            .collect()
+    }
+}
+
+/// Extract the host of a URL, or an empty string if it is malformed.
+fn host_of(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_owned))
+        .unwrap_or_default()
+}
@@ -1,60 +1,142 @@
 use std::{
-    collections::HashMap,
-    fs::{self, File},
+    collections::{BTreeSet, HashMap},
+    fs,
     io::{self, Write},
+    path::{Path, PathBuf},
     sync::{
         Arc,
         atomic::{self, Ordering::SeqCst},
     },
 };
 
-use data::{Driver, Episode};
+use changelog::Changelog;
+use clap::Parser;
+use cli::{Cli, Command};
+use config::{Config, PathsConfig, StatsConfig};
+use data::{Driver, Episode, Expansion, IncompleteFetch, Link};
+use episode::EpisodeId;
+use show_notes::ShowNotesStore;
 
+mod atomic_write;
+mod cache;
+mod canonical;
+mod changelog;
+mod cli;
+mod config;
 mod data;
+mod diagnostics;
+mod episode;
+mod http;
 mod paint;
+mod show_notes;
+#[cfg(feature = "sqlite")]
+mod sqlite;
 mod stats;
 
-const DATA_DIR: &str = "data";
-const EPISODES_DATA: &str = "data/episodes.json";
-const SHORT_URLS_DATA: &str = "data/short_urls.json";
-const OUT_DIR: &str = "out";
-const OUT_PAINT: &str = "out/typechat.dot";
-const OUT_STATS: &str = "out/external-links.md";
-const MIN_LINK_REF: i32 = 11;
+/// Load the config file, and apply overrides from the command line.
+fn load_config(cli: &Cli) -> io::Result<Config> {
+    let mut config = Config::load(cli.config.as_deref())?;
 
-fn load_driver() -> io::Result<Driver> {
+    if let Some(data_dir) = &cli.data_dir {
+        config.paths.data_dir = data_dir.to_owned();
+    }
+    if let Some(out_dir) = &cli.out_dir {
+        config.paths.out_dir = out_dir.to_owned();
+    }
+    if let Some(min_link_ref) = cli.min_link_ref {
+        config.stats.min_link_ref = min_link_ref;
+    }
+    if cli.incremental {
+        config.fetch.incremental = true;
+    }
+
+    Ok(config)
+}
+
+fn load_driver(paths: &PathsConfig) -> io::Result<Driver> {
     // Load episodes
-    let episodes = fs::read_to_string(EPISODES_DATA)
+    let episodes_path = paths.episodes();
+    let episodes = fs::read_to_string(&episodes_path)
         .inspect(|_| {
-            println!("Loading episodes from {EPISODES_DATA}…");
+            println!("Loading episodes from {}…", episodes_path.display());
         })
         .ok();
 
     // Load short URL cache
-    let short_urls = fs::read_to_string(SHORT_URLS_DATA)
+    let short_urls_path = paths.short_urls();
+    let short_urls = fs::read_to_string(&short_urls_path)
         .inspect(|_| {
-            println!("Loading short URL cache from {SHORT_URLS_DATA}…");
+            println!(
+                "Loading short URL cache from {}…",
+                short_urls_path.display()
+            );
         })
         .ok();
 
     Driver::from_cache(episodes, short_urls)
 }
 
-fn save_driver(driver: &Driver) -> io::Result<()> {
-    let (episodes, short_urls) = &driver.to_cache()?;
+/// [`load_driver`] without falling back to an empty driver, for offline use.
+fn load_cached_driver(paths: &PathsConfig) -> io::Result<Driver> {
+    let episodes_path = paths.episodes();
+    if !episodes_path.try_exists()? {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "episodes cache “{}” does not exist; fetch online first, or download data.7z",
+                episodes_path.display()
+            ),
+        ));
+    }
+
+    load_driver(paths)
+}
 
-    fs::create_dir_all(DATA_DIR)?;
+fn save_driver(driver: &Driver, config: &Config) -> io::Result<()> {
+    let paths = &config.paths;
+    let (episodes, short_urls) = &driver.to_cache(config.cache.pretty)?;
 
-    fs::write(EPISODES_DATA, episodes)?;
-    fs::write(SHORT_URLS_DATA, short_urls)?;
+    fs::create_dir_all(&paths.data_dir)?;
+
+    atomic_write::write(&paths.episodes(), episodes)?;
+    atomic_write::write(&paths.short_urls(), short_urls)?;
 
     Ok(())
 }
 
-fn fetch_data() -> Result<Driver, Box<dyn std::error::Error>> {
-    let mut driver = load_driver()?;
+/// Fetch episodes and update the data caches.
+///
+/// If some pages could not be fetched, the driver is still returned, together with an [`IncompleteFetch`].
+fn fetch_data(
+    config: &Config,
+) -> Result<(Driver, Option<IncompleteFetch>), Box<dyn std::error::Error>> {
+    let client = config
+        .http
+        .build_client(config.rate_limit.build_limiter())?;
+    let mut driver = load_driver(&config.paths)?
+        .with_client(client.clone())
+        .with_shorteners(config.shorteners.hosts.iter().cloned())
+        .with_expansion_retry(config.shorteners.retry_policy());
+    if config.cache.show_notes {
+        driver = driver.with_show_notes(ShowNotesStore::new(
+            config.paths.show_notes(),
+            config.cache.compress_show_notes,
+        ));
+    }
+    if let Some(path) = &config.paths.database {
+        driver = attach_database(driver, path)?;
+    }
 
-    let mut fetcher = data::Fetcher::build()?;
+    let mut fetcher = data::Fetcher::build(client, config.http.retry_policy())?;
+    if config.fetch.incremental {
+        fetcher = fetcher.incremental(
+            driver
+                .episodes
+                .keys()
+                .map(|ep| (ep.url.to_owned(), ep.clone()))
+                .collect(),
+        );
+    }
     println!("✅ Found {} episodes.", fetcher.len());
 
     // If Ctrl+C, stop updating episodes and [`save_driver`].
@@ -70,7 +152,7 @@ fn fetch_data() -> Result<Driver, Box<dyn std::error::Error>> {
         if running.load(SeqCst) {
             driver.push_episode(episode, &show_notes).inspect_err(|_| {
                 // Save eagerly
-                save_driver(&driver)
+                save_driver(&driver, config)
                     .inspect(|_| println!("cache saved after failure."))
                     .unwrap_or_else(|err| {
                         eprintln!("failed to save cache after failure: {err}");
@@ -81,69 +163,344 @@ fn fetch_data() -> Result<Driver, Box<dyn std::error::Error>> {
         }
     }
 
-    save_driver(&driver)?;
+    // Expand short URLs in a batch, unless interrupted
+    if running.load(SeqCst) {
+        driver.expand_short_urls(&config.http.worker_pool())?;
+    }
+
+    save_driver(&driver, config)?;
+    save_diagnostics(&driver, &config.paths)?;
+
+    let failed_pages = fetcher.failed_pages();
+    let incomplete = if failed_pages.is_empty() {
+        None
+    } else {
+        eprintln!("\n❌ Failed to fetch {} pages:", failed_pages.len());
+        for (page, err) in failed_pages {
+            eprintln!("- Page {page}: {err}");
+        }
+        Some(IncompleteFetch {
+            failed_pages: failed_pages.iter().map(|(page, _)| *page).collect(),
+        })
+    };
 
     if running.load(SeqCst) {
-        Ok(driver)
+        Ok((driver, incomplete))
     } else {
         panic!("shutdown by Ctrl+C.")
     }
 }
 
-fn save_stats(episodes: &HashMap<Episode, Vec<String>>) -> io::Result<()> {
-    println!("\nSaving to {OUT_STATS}…");
-    let mut file = File::create(OUT_STATS)?;
-    file.write_all(b"# Statistics of External Links\n\n")?;
-    let unsorted_stats = stats::count(episodes.values().flatten());
-    let mut sorted_stats: Vec<_> = unsorted_stats.iter().collect();
-    sorted_stats.sort_unstable_by(|a, b| a.1.cmp(b.1).reverse());
-    for (i, (domain, count)) in sorted_stats.iter().enumerate() {
-        if **count >= MIN_LINK_REF {
-            writeln!(
-                file,
-                "{i:02}. {:>3} [{}](https://{})",
-                **count,
-                stats::humanize(domain),
-                domain
-            )?;
+/// Write episodes to the SQLite database at `path` as well.
+#[cfg(feature = "sqlite")]
+fn attach_database(driver: Driver, path: &Path) -> Result<Driver, Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    println!("Writing to database {}…", path.display());
+    Ok(driver.with_database(sqlite::Database::open(path)?)?)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn attach_database(_driver: Driver, _path: &Path) -> Result<Driver, Box<dyn std::error::Error>> {
+    Err("`paths.database` requires building with the `sqlite` feature".into())
+}
+
+/// Save anomalies found in show notes, replacing the old report.
+///
+/// Skipped unless every episode has been checked in this run,
+/// because unchanged episodes are not checked again, and their anomalies would be lost.
+fn save_diagnostics(driver: &Driver, paths: &PathsConfig) -> io::Result<()> {
+    let path = paths.diagnostics();
+    let episodes = driver.episodes.keys().map(|ep| ep.url.as_str());
+    if !driver.diagnostics.covers(episodes) {
+        if !driver.diagnostics.is_empty() {
+            println!(
+                "\n⚠️ Found {} anomalies, but not every episode is checked in this run. Keeping {} as is; run `reextract` to update it.",
+                driver.diagnostics.len(),
+                path.display()
+            );
         }
+        return Ok(());
     }
-    writeln!(
-        file,
-        "\nLinks with less than {} references are omitted.",
-        MIN_LINK_REF
-    )?;
 
-    Ok(())
+    if driver.diagnostics.is_empty() {
+        // Remove the report of a previous run, which is stale now
+        return match fs::remove_file(&path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+    }
+
+    fs::create_dir_all(&paths.out_dir)?;
+
+    println!(
+        "\n⚠️ Found {} anomalies in show notes. Saving to {}…",
+        driver.diagnostics.len(),
+        path.display()
+    );
+    atomic_write::write_with(&path, |file| driver.diagnostics.write_report(file))
 }
 
-fn save_paint(episodes: HashMap<Episode, Vec<String>>) -> io::Result<()> {
+/// Count domains of links, most referred first.
+fn rank_domains<'a>(
+    episodes: &'a HashMap<Episode, Vec<Link>>,
+    config: &StatsConfig,
+) -> Vec<(&'a str, i32)> {
+    // Count each canonical URL once per episode
+    let unsorted_stats = stats::count(episodes.values().flat_map(|links| {
+        links
+            .iter()
+            .filter(|l| config.roles.contains(&l.role))
+            .map(Link::key)
+            .collect::<BTreeSet<_>>()
+    }));
+    let mut sorted_stats: Vec<_> = unsorted_stats.into_iter().collect();
+    sorted_stats.sort_unstable_by(|a, b| a.1.cmp(&b.1).reverse().then(a.0.cmp(b.0)));
+    sorted_stats
+}
+
+fn save_stats(driver: &Driver, config: &Config) -> io::Result<()> {
+    let episodes = &driver.episodes;
+    let min_link_ref = config.stats.min_link_ref;
+
+    fs::create_dir_all(&config.paths.out_dir)?;
+
+    let path = config.paths.stats();
+    println!("\nSaving to {}…", path.display());
+    atomic_write::write_with(&path, |file| {
+        file.write_all(b"# Statistics of External Links\n\n")?;
+        for (i, (domain, count)) in rank_domains(episodes, &config.stats).iter().enumerate() {
+            if *count >= min_link_ref {
+                writeln!(
+                    file,
+                    "{i:02}. {:>3} [{}](https://{})",
+                    count,
+                    stats::humanize(domain),
+                    domain
+                )?;
+            }
+        }
+        writeln!(
+            file,
+            "\nLinks with less than {min_link_ref} references are omitted."
+        )?;
+
+        Ok(())
+    })?;
+
+    save_short_urls(driver, &config.paths)
+}
+
+/// Save expanded short URLs, separately from the ranking.
+fn save_short_urls(driver: &Driver, paths: &PathsConfig) -> io::Result<()> {
+    let chains: Vec<_> = driver
+        .short_urls()
+        .filter_map(|(url, expansion)| match expansion {
+            Expansion::Resolved(chain) => Some((
+                url,
+                chain.first_hop()?,
+                chain.destination()?,
+                chain.hops.len(),
+            )),
+            Expansion::Failed { .. } => None,
+        })
+        .collect();
+
+    let path = paths.short_urls_report();
+    println!("\nSaving to {}…", path.display());
+    atomic_write::write_with(&path, |file| {
+        file.write_all(b"# Short URLs\n\n")?;
+        for (url, first_hop, destination, n_hops) in chains {
+            match n_hops {
+                1 => writeln!(file, "- <{url}> → <{destination}>")?,
+                2 => writeln!(file, "- <{url}> → <{first_hop}> → <{destination}>")?,
+                _ => writeln!(file, "- <{url}> → <{first_hop}> → … → <{destination}>")?,
+            }
+        }
+        Ok(())
+    })
+}
+
+fn save_paint(episodes: HashMap<Episode, Vec<Link>>, config: &Config) -> io::Result<()> {
     let mut catalog: Vec<_> = episodes.keys().cloned().collect();
     // Sort to `paint` better
-    catalog.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    catalog.sort_by_cached_key(Episode::id);
 
     let links: Vec<_> = episodes
         .into_iter()
         .flat_map(|(ep, links)| {
+            // Deduplicate by canonical URLs
             links
+                .iter()
+                .filter(|l| config.graph.roles.contains(&l.role))
+                .map(Link::key)
+                .collect::<BTreeSet<_>>()
                 .into_iter()
                 .map(|to_url| paint::Link {
-                    from_url: ep.url.to_owned(),
-                    to_url,
+                    from: ep.id(),
+                    to_url: to_url.to_owned(),
                 })
                 .collect::<Vec<_>>()
         })
         .collect();
 
-    println!("\nSaving to {OUT_PAINT}…");
-    let file = File::create(OUT_PAINT)?;
-    paint::paint(&catalog, &links, file)?;
+    fs::create_dir_all(&config.paths.out_dir)?;
+
+    let path = config.paths.paint();
+    println!("\nSaving to {}…", path.display());
+    atomic_write::write_with(&path, |file| {
+        paint::paint(&catalog, &links, &config.graph, file)
+    })
+}
+
+/// Extract links again from stored show notes, and report changed episodes.
+fn reextract(config: &Config) -> Result<Driver, Box<dyn std::error::Error>> {
+    let mut driver = load_cached_driver(&config.paths)?;
+    if let Some(path) = &config.paths.database {
+        driver = attach_database(driver, path)?;
+    }
+
+    let store = ShowNotesStore::new(config.paths.show_notes(), config.cache.compress_show_notes);
+    let result = driver.reextract(&store)?;
+
+    if !result.missing.is_empty() {
+        eprintln!(
+            "\n⚠️ Show notes of {} episodes are not stored, and their links are kept as is. Fetch online to store them.",
+            result.missing.len()
+        );
+    }
+
+    println!("\n✅ Links changed in {} episodes.", result.changed.len());
+    for (ep, changes) in &result.changed {
+        if changes.is_empty() {
+            println!(
+                "\n{} <{}>: only texts, positions or roles changed",
+                ep.title(),
+                ep.url
+            );
+            continue;
+        }
+
+        println!(
+            "\n{} <{}>: +{} −{}",
+            ep.title(),
+            ep.url,
+            changes.added.len(),
+            changes.removed.len()
+        );
+        for url in &changes.added {
+            println!("+ {url}");
+        }
+        for url in &changes.removed {
+            println!("- {url}");
+        }
+    }
+
+    save_driver(&driver, config)?;
+    save_diagnostics(&driver, &config.paths)?;
+
+    Ok(driver)
+}
+
+/// Compare two versions of the episodes cache, and save a changelog.
+///
+/// By default, compare the backup of the episodes cache with the current one.
+fn diff(
+    old: Option<PathBuf>,
+    new: Option<PathBuf>,
+    json: bool,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let old = old.unwrap_or_else(|| atomic_write::backup_path(&config.paths.episodes()));
+    let new = new.unwrap_or_else(|| config.paths.episodes());
+
+    let load = |path: &Path| -> io::Result<_> {
+        println!("Loading episodes from {}…", path.display());
+        let episodes = fs::read_to_string(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+        Ok(Driver::from_cache(Some(episodes), None)?.episodes)
+    };
+    let old = load(&old)?;
+    let new = load(&new)?;
+
+    // Domains listed in `external-links.md`
+    let top = |episodes| {
+        let mut ranking = rank_domains(episodes, &config.stats);
+        ranking.retain(|(_, count)| *count >= config.stats.min_link_ref);
+        ranking
+    };
+    let changelog = Changelog::between(&old, &new, &top(&old), &top(&new));
+
+    println!(
+        "\n✅ {} new episodes, {} removed episodes, and {} episodes with changed links.",
+        changelog.new_episodes.len(),
+        changelog.removed_episodes.len(),
+        changelog.changed_episodes.len()
+    );
+
+    fs::create_dir_all(&config.paths.out_dir)?;
+
+    let path = config.paths.changelog(json);
+    println!("\nSaving to {}…", path.display());
+    atomic_write::write_with(&path, |file| {
+        if json {
+            serde_json::to_writer_pretty(&mut *file, &changelog)?;
+            Ok(file.write_all(b"\n")?)
+        } else {
+            changelog.write_markdown(file)
+        }
+    })?;
 
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let driver = fetch_data()?;
+/// Print episodes and links that match `pattern`.
+///
+/// With `by_episode`, `pattern` may also be an episode ID, e.g. `250` or `ep-250`.
+fn query(episodes: &HashMap<Episode, Vec<Link>>, pattern: &str, by_episode: bool) {
+    let id: EpisodeId = pattern
+        .parse()
+        .expect("parsing episode IDs should be infallible");
+    let mut matches: Vec<_> = episodes
+        .iter()
+        .filter_map(|(ep, links)| {
+            let links: Vec<_> = if by_episode {
+                if ep.id() == id || ep.name.contains(pattern) || ep.url.contains(pattern) {
+                    links.iter().collect()
+                } else {
+                    vec![]
+                }
+            } else {
+                links
+                    .iter()
+                    .filter(|l| l.url.contains(pattern) || l.key().contains(pattern))
+                    .collect()
+            };
+            (!links.is_empty()).then_some((ep, links))
+        })
+        .collect();
+    matches.sort_by_cached_key(|(ep, _)| ep.id());
+
+    for (ep, links) in &matches {
+        println!("\n{} <{}>", ep.title(), ep.url);
+        for l in links {
+            if l.text.is_empty() {
+                println!("- {}", l.url);
+            } else {
+                println!("- {} “{}”", l.url, l.text);
+            }
+        }
+    }
+
+    println!(
+        "\n✅ Found {} links in {} episodes.",
+        matches.iter().map(|(_, links)| links.len()).sum::<usize>(),
+        matches.len()
+    );
+}
+
+fn count_links(driver: &Driver) {
     println!(
         "\n✅ Found {} links.",
         driver
@@ -152,10 +509,59 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .map(|links| links.len())
             .sum::<usize>()
     );
+}
 
-    fs::create_dir_all(OUT_DIR)?;
-    save_stats(&driver.episodes)?;
-    save_paint(driver.episodes)?;
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let config = load_config(&cli)?;
+
+    match cli.command.unwrap_or(Command::All) {
+        Command::Fetch => {
+            if cli.offline {
+                return Err("cannot fetch in offline mode".into());
+            }
+
+            let (driver, incomplete) = fetch_data(&config)?;
+            count_links(&driver);
+
+            if let Some(incomplete) = incomplete {
+                return Err(incomplete.into());
+            }
+        }
+        Command::Stats => {
+            let driver = load_cached_driver(&config.paths)?;
+            save_stats(&driver, &config)?;
+        }
+        Command::Paint => {
+            let driver = load_cached_driver(&config.paths)?;
+            save_paint(driver.episodes, &config)?;
+        }
+        Command::All => {
+            let (driver, incomplete) = if cli.offline {
+                (load_cached_driver(&config.paths)?, None)
+            } else {
+                fetch_data(&config)?
+            };
+            count_links(&driver);
+
+            save_stats(&driver, &config)?;
+            save_paint(driver.episodes, &config)?;
+
+            // Outputs are still saved, but the run should not look successful
+            if let Some(incomplete) = incomplete {
+                return Err(incomplete.into());
+            }
+        }
+        Command::Reextract => {
+            let driver = reextract(&config)?;
+            count_links(&driver);
+        }
+        Command::Query { pattern, episode } => {
+            let driver = load_cached_driver(&config.paths)?;
+            query(&driver.episodes, &pattern, episode);
+        }
+        Command::Diff { old, new, json } => diff(old, new, json, &config)?,
+    }
 
     Ok(())
 }
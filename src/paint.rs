@@ -1,65 +1,115 @@
 //! Paint in-TypeChat links using Graphviz dot.
 
-use std::{collections::HashSet, io};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io,
+};
 
-use random_color::{RandomColor, options::Luminosity};
+use random_color::RandomColor;
 
-use crate::data::Episode;
+use crate::{canonical::canonicalize, config::GraphConfig, data::Episode, episode::EpisodeId};
 
 #[derive(Debug)]
 pub struct Link {
-    pub from_url: String,
+    pub from: EpisodeId,
     pub to_url: String,
 }
 
-/// Extract the number in a TypeChat URL
-fn typechat_number(url: &str) -> Option<&str> {
-    Some(
-        url.strip_prefix("https://www.thetype.com/typechat/ep-")?
-            .trim_end_matches("/"),
-    )
+/// Name of an episode’s node, e.g. `typechat_250`
+fn node_name(id: &EpisodeId) -> String {
+    match id {
+        EpisodeId::Numbered(number) => format!("typechat_{number:03}"),
+        // Quote irregular names
+        _ => format!("\"typechat_{}\"", id.to_string().trim_start_matches("ep-")),
+    }
 }
 
 /// Export episodes and links to a Graphviz dot file
 pub fn paint(
     episodes: &Vec<Episode>,
     links: &Vec<Link>,
+    options: &GraphConfig,
     mut buffer: impl io::Write,
 ) -> io::Result<()> {
-    buffer.write_all(b"digraph {\nrankdir=LR\n")?;
+    buffer.write_all(format!("digraph {{\nrankdir={}\n", options.rankdir).as_bytes())?;
+
+    // Resolve targets through the catalog, so that edges and nodes agree on IDs
+    let catalog: HashMap<String, EpisodeId> = episodes
+        .iter()
+        .map(|e| {
+            (
+                canonicalize(&e.url).unwrap_or_else(|| e.url.clone()),
+                e.id(),
+            )
+        })
+        .collect();
+    let resolve = |url: &str| {
+        canonicalize(url)
+            .and_then(|url| catalog.get(&url).cloned())
+            // Episodes missing from the catalog, e.g. in pages failed to fetch
+            .or_else(|| EpisodeId::from_url(url))
+    };
 
     // Paint in-TypeChat links (edges) and record mentioned episodes
-    let mut mentioned_url = HashSet::new();
+    let mut mentioned = HashSet::new();
     for l in links {
-        if let Some(to_number) = typechat_number(&l.to_url) {
-            let from_number =
-                typechat_number(&l.from_url).expect("a link should start from a typechat URL");
+        if let Some(to) = resolve(&l.to_url) {
             buffer.write_all(
                 format!(
-                    "typechat_{from_number} -> typechat_{to_number} [color=\"{color}\"]\n",
+                    "{from} -> {to} [color=\"{color}\"]\n",
+                    from = node_name(&l.from),
+                    to = node_name(&to),
                     // To be more distinguishable
                     color = RandomColor::new()
-                        .luminosity(Luminosity::Light)
+                        .luminosity(options.edge_luminosity.into())
                         .seed(&l.to_url)
                         .to_hex()
                 )
                 .as_bytes(),
             )?;
 
-            mentioned_url.insert(&l.from_url);
-            mentioned_url.insert(&l.to_url);
+            mentioned.insert(l.from.clone());
+            mentioned.insert(to);
         }
     }
 
-    // Paint mentioned episodes (nodes)
+    // Paint mentioned episodes (nodes), and collect multi-part ones
+    let mut multi_parts: BTreeMap<&str, Vec<String>> = BTreeMap::new();
     for e in episodes {
-        if mentioned_url.contains(&e.url) {
+        let id = e.id();
+        if mentioned.contains(&id) {
+            let title = e.title();
+            let label = title.to_string();
             buffer.write_all(
                 format!(
-                    "typechat_{number}[href=\"{href}\" label=\"{label}\"]\n",
-                    label = e.name.replace("：", "\\n"),
+                    "{node}[href=\"{href}\" label=\"{label}\"]\n",
+                    // Put the number in its own line
+                    label = match title.number {
+                        Some(_) => label.replacen(' ', "\\n", 1),
+                        None => label,
+                    },
+                    node = node_name(&id),
                     href = e.url,
-                    number = typechat_number(&e.url).expect("an episode's URL should be regular"),
+                )
+                .as_bytes(),
+            )?;
+
+            if title.is_multi_part() {
+                multi_parts
+                    .entry(title.main)
+                    .or_default()
+                    .push(node_name(&id));
+            }
+        }
+    }
+
+    // Group parts of the same episode
+    for (main, nodes) in multi_parts {
+        if nodes.len() > 1 {
+            buffer.write_all(
+                format!(
+                    "subgraph \"cluster_{main}\" {{\nlabel=\"{main}\"\n{}\n}}\n",
+                    nodes.join(" ")
                 )
                 .as_bytes(),
             )?;
@@ -0,0 +1,101 @@
+//! Keep the raw HTML of show notes, so that links can be extracted again without the Internet.
+//!
+//! Each episode has a file named after the slug of its URL, e.g. `ep-250.html`, or `ep-250.html.gz` if compressed.
+//! Episodes without a slug fall back to their post IDs, e.g. `post-1234.html`.
+//!
+//! [`EpisodeId`](crate::episode::EpisodeId) is not used, because irregular episodes may share one.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::PathBuf,
+};
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+
+use crate::{atomic_write, data::Episode};
+
+/// A directory of show notes.
+#[derive(Debug, Clone)]
+pub struct ShowNotesStore {
+    dir: PathBuf,
+    /// Compress new files with gzip
+    compress: bool,
+}
+
+impl ShowNotesStore {
+    pub fn new(dir: PathBuf, compress: bool) -> Self {
+        Self { dir, compress }
+    }
+
+    fn path(&self, episode: &Episode, compressed: bool) -> io::Result<PathBuf> {
+        let name = match (episode.slug(), episode.post_id) {
+            (Some(slug), _) => slug,
+            (None, Some(post_id)) => format!("post-{post_id}"),
+            (None, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("no slug or post ID to name show notes of {}", episode.url),
+                ));
+            }
+        };
+        let extension = if compressed { "html.gz" } else { "html" };
+        Ok(self.dir.join(format!("{name}.{extension}")))
+    }
+
+    /// Load the show notes of an episode, either compressed or not.
+    ///
+    /// Return `None` if it has not been stored.
+    pub fn load(&self, episode: &Episode) -> io::Result<Option<String>> {
+        let mut html = String::new();
+        match File::open(self.path(episode, true)?) {
+            Ok(file) => {
+                GzDecoder::new(file).read_to_string(&mut html)?;
+                return Ok(Some(html));
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        match fs::read_to_string(self.path(episode, false)?) {
+            Ok(html) => Ok(Some(html)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Store the show notes of an episode, unless they are stored already.
+    ///
+    /// Return whether the file is written.
+    pub fn save(&self, episode: &Episode, html: &str) -> io::Result<bool> {
+        if self.load(episode)?.is_some_and(|old| old == html) {
+            return Ok(false);
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        // The HTML can be fetched again, so no backup is kept
+        let path = self.path(episode, self.compress)?;
+        if self.compress {
+            atomic_write::write_with_no_backup(&path, |file| {
+                let mut encoder = GzEncoder::new(file, Compression::default());
+                encoder.write_all(html.as_bytes())?;
+                encoder.finish().map(|_| ())
+            })?;
+        } else {
+            atomic_write::write_with_no_backup(&path, |file| file.write_all(html.as_bytes()))?;
+        }
+
+        // Remove the file in the other format, which is stale now, and backups left by earlier versions
+        let other = self.path(episode, !self.compress)?;
+        for stale in [
+            atomic_write::backup_path(&path),
+            atomic_write::backup_path(&other),
+            other,
+        ] {
+            match fs::remove_file(stale) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(true)
+    }
+}
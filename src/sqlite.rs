@@ -0,0 +1,166 @@
+//! Store data in an SQLite database, to be queried with plain SQL.
+//!
+//! The JSON caches are still the source of truth. The database is written along with them, episode by episode.
+
+use std::path::Path;
+
+use rusqlite::{Connection, Transaction, params};
+use serde::Serialize;
+
+use crate::{
+    canonical::canonicalize,
+    data::{Episode, Expansion, Link},
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS episodes (
+    url TEXT PRIMARY KEY,
+    -- e.g. `ep-250`
+    slug TEXT NOT NULL,
+    -- NULL for special episodes
+    number INTEGER,
+    name TEXT NOT NULL,
+    post_id INTEGER,
+    -- ISO 8601
+    date TEXT,
+    modified TEXT,
+    audio_url TEXT,
+    duration TEXT,
+    -- JSON arrays of strings
+    categories TEXT NOT NULL,
+    tags TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS links (
+    episode_url TEXT NOT NULL REFERENCES episodes (url) ON DELETE CASCADE,
+    -- Position among links in the show notes, counting from 0
+    idx INTEGER NOT NULL,
+    href TEXT NOT NULL,
+    url TEXT NOT NULL,
+    canonical TEXT,
+    text TEXT NOT NULL,
+    context TEXT,
+    section TEXT,
+    -- `reference`, `subscription`, or `media`
+    role TEXT NOT NULL,
+    PRIMARY KEY (episode_url, idx)
+);
+CREATE INDEX IF NOT EXISTS links_canonical ON links (canonical);
+
+CREATE TABLE IF NOT EXISTS short_urls (
+    url TEXT PRIMARY KEY,
+    -- NULL if the expansion failed
+    destination TEXT,
+    -- The whole expansion in JSON, as in `short_urls.json`
+    expansion TEXT NOT NULL
+);
+";
+
+/// An SQLite database of episodes, links and short URLs.
+#[derive(Debug)]
+pub struct Database {
+    conn: Connection,
+}
+
+impl Database {
+    /// Open the database at `path`, and create tables if necessary.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Save an episode and its links, replacing the old ones if any.
+    pub fn save_episode(&mut self, episode: &Episode, links: &[Link]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        insert_episode(&tx, episode, links)?;
+        tx.commit()
+    }
+
+    /// Replace all episodes and links, so that the database matches the caches.
+    pub fn replace_episodes<'a>(
+        &mut self,
+        episodes: impl Iterator<Item = (&'a Episode, &'a Vec<Link>)>,
+    ) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM episodes", [])?;
+        for (episode, links) in episodes {
+            insert_episode(&tx, episode, links)?;
+        }
+        tx.commit()
+    }
+
+    /// Save expansions of short URLs, and update links to their destinations.
+    pub fn save_short_urls<'a>(
+        &mut self,
+        short_urls: impl Iterator<Item = (&'a str, &'a Expansion)>,
+    ) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut upsert = tx.prepare(
+                "INSERT OR REPLACE INTO short_urls (url, destination, expansion)
+                    VALUES (?1, ?2, ?3)",
+            )?;
+            let mut update_links = tx.prepare(
+                "UPDATE links SET url = ?2, canonical = ?3 WHERE url = ?1 AND url != ?2",
+            )?;
+            for (url, expansion) in short_urls {
+                let destination = expansion.destination();
+                upsert.execute(params![url, destination, to_json(expansion)])?;
+                if let Some(destination) = destination {
+                    update_links.execute(params![url, destination, canonicalize(destination)])?;
+                }
+            }
+        }
+        tx.commit()
+    }
+}
+
+/// Insert an episode and its links, replacing the old ones if any.
+fn insert_episode(tx: &Transaction, episode: &Episode, links: &[Link]) -> rusqlite::Result<()> {
+    let id = episode.id();
+
+    tx.execute("DELETE FROM episodes WHERE url = ?1", [&episode.url])?;
+    tx.execute(
+        "INSERT INTO episodes
+            (url, slug, number, name, post_id, date, modified, audio_url, duration, categories, tags)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            episode.url,
+            id.to_string(),
+            id.number(),
+            episode.name,
+            episode.post_id.map(|id| id as i64),
+            episode.date,
+            episode.modified,
+            episode.audio_url,
+            episode.duration,
+            to_json(&episode.categories),
+            to_json(&episode.tags),
+        ],
+    )?;
+    let mut insert = tx.prepare_cached(
+        "INSERT INTO links
+            (episode_url, idx, href, url, canonical, text, context, section, role)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+    for l in links {
+        insert.execute(params![
+            episode.url,
+            l.index as i64,
+            l.href,
+            l.url,
+            l.canonical,
+            l.text,
+            l.context,
+            l.section,
+            to_json(&l.role).trim_matches('"'),
+        ])?;
+    }
+    Ok(())
+}
+
+fn to_json(value: &impl Serialize) -> String {
+    serde_json::to_string(value).expect("serializing to JSON should not fail")
+}
@@ -4,21 +4,15 @@ use std::collections::HashMap;
 
 /// Simplify an external URL
 ///
+/// The URL should be canonicalized if possible. (see [`crate::canonical`])
+/// Links in the footer should be filtered by [`crate::data::LinkRole`] beforehand.
+///
 /// Return `Some(domain)` for pertinent URLs, return `None` otherwise.
 fn normalize(url: &str) -> Option<&str> {
     // Match constants
     let irrelevant = match url {
-        // 各平台《字谈字畅》
-        "https://www.thetype.com/typechat/feed/"
-        | "http://www.lizhi.fm/1852153/"
-        | "http://music.163.com/#/djradio?id=346541057"
-        | "https://www.thetype.com/feed/typechat/"
-        | "https://itunes.apple.com/cn/podcast/zi-tan-zi-chang/id1041704528"
-        | "https://podcasts.apple.com/cn/podcast/%E5%AD%97%E8%B0%88%E5%AD%97%E7%95%85/id1041704528"
-        | "https://itunes.apple.com/cn/podcast/%E5%AD%97%E8%B0%88%E5%AD%97%E7%95%85/id1041704528"
-        | "https://static.thetype.cloud/typechat/assets/typechat-weapp.jpg" => true,
         // 会员
-        "https://www.thetype.com/members/" => true,
+        "https://www.thetype.com/members" => true,
         // 特例
         // 写真歴史博物館——https://www.thetype.com/typechat/ep-039/
         "%E5%86%99%E7%9C%9F%E6%AD%B4%E5%8F%B2%E5%8D%9A%E7%89%A9%E9%A4%A8" => true,
@@ -31,8 +25,6 @@ fn normalize(url: &str) -> Option<&str> {
     // Cloudflare email protection, or a literal email
     if url.starts_with("/cdn-cgi/l/email-protection")
         || url.starts_with("mailto:")
-        // Audio
-        || (url.starts_with("https://static.thetype.cloud/typechat/") && url.ends_with(".mp3"))
         // Images and files
         || url.starts_with("https://static.thetype.cloud/typechat/assets/")
     {
@@ -47,8 +39,6 @@ fn normalize(url: &str) -> Option<&str> {
         // Aliases, remove `www.`, etc.
         let domain = match domain {
             "www.unicode.org" => "unicode.org",
-            "youtu.be" => "www.youtube.com",
-            "adobe.ly" => "www.adobe.com",
             "events.bizzabo.com" => "atypi.org",
             "www.atypi.org" => "atypi.org",
             "atypi2018.dryfta.com" => "atypi.org",
@@ -128,7 +118,7 @@ pub fn humanize(url: &str) -> &str {
 }
 
 /// Count links' references
-pub fn count<'a>(links: impl Iterator<Item = &'a String>) -> HashMap<&'a str, i32> {
+pub fn count<'a>(links: impl Iterator<Item = &'a str>) -> HashMap<&'a str, i32> {
     let mut stats = HashMap::new();
 
     for l in links {